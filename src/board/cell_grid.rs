@@ -0,0 +1,180 @@
+// The cells of a playing area or preview, drawn in one go from a colour for each cell
+//
+// This used to be a GtkGrid with a widget for every cell, which came to thousands of widgets with several big boards
+// and had to be restyled cell by cell each time rows were removed. Now the colours are kept here and each cell is
+// drawn by rendering a sample widget in its place, one sample for each colour. The samples are styled by the same CSS
+// classes the cells used to have (the piece name, or "empty", around a "cell"), so style sheets work as before.
+
+use gtk::glib;
+
+glib::wrapper! {
+    pub struct CellGrid(ObjectSubclass<imp::CellGrid>)
+    @extends gtk::Widget;
+}
+
+// colour 0 is an empty cell, the rest are the pieces in POS order
+pub const EMPTY: u8 = 0;
+
+impl CellGrid {
+    pub fn new() -> Self { glib::Object::builder().build() }
+}
+
+impl Default for CellGrid {
+    fn default() -> Self { Self::new() }
+}
+
+pub mod imp {
+    use std::cell::{Cell, RefCell};
+
+    use super::EMPTY;
+    use crate::board::imp::piece_names;
+
+    use gtk::glib;
+    use gtk::graphene;
+    use gtk::prelude::*;
+    use gtk::subclass::prelude::*;
+
+    #[derive(Debug, Default)]
+    pub struct CellGrid {
+        columns: Cell<u32>,
+        rows:    Cell<u32>,
+        colors:  RefCell<Vec<u8>>,          // row by row from the top left
+        names:   RefCell<Vec<&'static str>>,    // the colours, "empty" and then the piece names
+        samples: RefCell<Vec<gtk::Widget>>, // one for each colour
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for CellGrid {
+        const NAME: &'static str = "CellGrid";
+        type Type = super::CellGrid;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.set_css_name("cellgrid");
+        }
+    }
+
+    impl ObjectImpl for CellGrid {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let names: Vec<&'static str> = std::iter::once("empty").chain(piece_names()).collect();
+            // the samples are only drawn from, events go to the grid
+            let samples: Vec<gtk::Widget> = names.iter().map(|name| {
+                let sample = make_cell(name);
+                sample.set_can_target(false);
+                sample.set_parent(&*self.obj());
+                sample.upcast()
+            }).collect();
+            *self.names.borrow_mut() = names;
+            *self.samples.borrow_mut() = samples;
+        }
+
+        fn dispose(&self) {
+            self.samples.borrow_mut().drain(..).for_each(|sample| sample.unparent());
+        }
+    }
+
+    impl WidgetImpl for CellGrid {
+        // the samples all have the same size, which is the size of a cell
+        fn measure(&self, orientation: gtk::Orientation, _for_size: i32) -> (i32, i32, i32, i32) {
+            let count = match orientation {
+                gtk::Orientation::Horizontal => self.columns.get(),
+                _ => self.rows.get(),
+            } as i32;
+            let (min, natural) = match self.samples.borrow().first() {
+                Some(sample) => { let (min, natural, _, _) = sample.measure(orientation, -1); (min, natural) },
+                None => (0, 0),
+            };
+            (min * count, natural * count, -1, -1)
+        }
+
+        // the cells share out whatever space there is
+        fn size_allocate(&self, width: i32, height: i32, _baseline: i32) {
+            if self.columns.get() == 0 || self.rows.get() == 0 { return; }
+            let (cell_width, cell_height) = self.cell_size(width, height);
+            for sample in self.samples.borrow().iter() {
+                sample.allocate(cell_width, cell_height, -1, None);
+            }
+        }
+
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            let (columns, rows) = (self.columns.get(), self.rows.get());
+            if columns == 0 || rows == 0 { return; }
+            let (cell_width, cell_height) = self.cell_size(self.obj().width(), self.obj().height());
+            let samples = self.samples.borrow();
+            let colors = self.colors.borrow();
+            for row in 0..rows {
+                for column in 0..columns {
+                    let Some(sample) = samples.get(colors[(row * columns + column) as usize] as usize) else { continue; };
+                    snapshot.save();
+                    snapshot.translate(&graphene::Point::new((column as i32 * cell_width) as f32, (row as i32 * cell_height) as f32));
+                    self.obj().snapshot_child(sample, snapshot);
+                    snapshot.restore();
+                }
+            }
+        }
+    }
+
+    impl CellGrid {
+        // all the cells start empty
+        pub fn set_size(&self, columns: u32, rows: u32) {
+            self.columns.set(columns);
+            self.rows.set(rows);
+            *self.colors.borrow_mut() = vec![EMPTY; (columns * rows) as usize];
+            self.obj().queue_resize();
+        }
+
+        // The colour name (piece name or "empty") of a cell, which is also its CSS class. Cells off the grid are None.
+        pub fn color(&self, column: i32, row: i32) -> Option<&'static str> {
+            let index = self.index(column, row)?;
+            let color = self.colors.borrow()[index];
+            self.names.borrow().get(color as usize).copied()
+        }
+
+        // returns false if the cell is off the grid. An unknown NAME is drawn as empty.
+        pub fn set_color(&self, column: i32, row: i32, name: &str) -> bool {
+            let Some(index) = self.index(column, row) else { return false; };
+            let color = self.names.borrow().iter().position(|n| *n == name).map_or(EMPTY, |i| i as u8);
+            let mut colors = self.colors.borrow_mut();
+            if colors[index] != color {
+                colors[index] = color;
+                self.obj().queue_draw();
+            }
+            true
+        }
+
+        // moves everything above ROW down one, covering it, and empties the top row
+        pub fn remove_row(&self, row: i32) {
+            if row < 0 || row >= self.rows.get() as i32 { return; }
+            let columns = self.columns.get() as usize;
+            let end = (row as usize + 1) * columns;
+            let mut colors = self.colors.borrow_mut();
+            colors.copy_within(0..end - columns, columns);
+            colors[..columns].fill(EMPTY);
+            self.obj().queue_draw();
+        }
+
+        fn index(&self, column: i32, row: i32) -> Option<usize> {
+            let (columns, rows) = (self.columns.get() as i32, self.rows.get() as i32);
+            if column < 0 || column >= columns || row < 0 || row >= rows { return None; }
+            Some((row * columns + column) as usize)
+        }
+
+        fn cell_size(&self, width: i32, height: i32) -> (i32, i32) {
+            (width / (self.columns.get() as i32).max(1), height / (self.rows.get() as i32).max(1))
+        }
+    }
+
+    // A sample cell: the colour is the background of the box and the label draws the border and sets the size
+    fn make_cell(name: &str) -> gtk::Box {
+        let cell = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .build();
+        cell.add_css_class(name);
+        let label = gtk::Label::builder()
+            .build();
+        label.add_css_class("cell");
+        cell.append(&label);
+        cell
+    }
+}
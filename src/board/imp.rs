@@ -1,5 +1,7 @@
 //use self::glib::{BindingFlags, ParamSpec, ParamSpecInt, Value};
 use crate::board;
+use super::cell_grid::CellGrid;
+use crate::board_count;
 use crate::controller_inst;
 use fastrand;
 use std::cell::{Cell, RefCell};
@@ -12,8 +14,8 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::glib::subclass::Signal;
 use gtk::glib::clone;
-use gtk::prelude::GridExt;
 use gtk::EventControllerMotion;
+use serde::{Deserialize, Serialize};
 
 //
 // Boilerplate
@@ -25,12 +27,13 @@ pub struct Board {
     pub width_oc:        OnceCell<u32>,
     pub height_oc:       OnceCell<u32>,
     pub show_preview_oc: OnceCell<bool>,
+    pub linked_oc:       OnceCell<Option<LineRule>>,
     internal:            Rc<RefCell<Internal>>,
 
     #[template_child]
-    pub playing_area: TemplateChild<gtk::Grid>,
+    pub playing_area: TemplateChild<CellGrid>,
     #[template_child]
-    pub preview: TemplateChild<gtk::Grid>,
+    pub preview: TemplateChild<CellGrid>,
     #[template_child]
     pub points: TemplateChild<gtk::Label>,
     #[template_child]
@@ -79,6 +82,7 @@ impl ObjectSubclass for Board {
     type Type = super::Board;
     type ParentType = gtk::Box;
     fn class_init(klass: &mut Self::Class) {
+        CellGrid::ensure_type();
         klass.bind_template();
     }
     fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -103,30 +107,98 @@ impl ObjectImpl for Board {
         self.parent_constructed();
         let this = self;
 		// Does this really need 3 separate handlers? The event doesn't seem to supply the button
+		// The clicks don't claim the event, that would cancel a drag starting with the same press
         let gesture_left = gtk::GestureClick::new();
         gesture_left.connect_pressed(clone!(@weak this => move |gesture, _x, _y, _z| {
-            gesture.set_state(gtk::EventSequenceState::Claimed);
-            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &0u32]);
+            if is_touch(gesture) { return; }
+            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &0u32, &gesture.current_event_state().bits()]);
         }));
         gesture_left.set_button(gtk::gdk::ffi::GDK_BUTTON_PRIMARY as u32);
         self.obj().add_controller(&gesture_left);
 
         let gesture_middle = gtk::GestureClick::new();
         gesture_middle.connect_pressed(clone!(@weak this => move |gesture, _x, _y, _z| {
-            gesture.set_state(gtk::EventSequenceState::Claimed);
-            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &1u32]);
+            if is_touch(gesture) { return; }
+            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &1u32, &gesture.current_event_state().bits()]);
         }));
         gesture_middle.set_button(gtk::gdk::ffi::GDK_BUTTON_MIDDLE as u32);
         self.obj().add_controller(&gesture_middle);
 
         let gesture_right = gtk::GestureClick::new();
         gesture_right.connect_pressed(clone!(@weak this => move |gesture, _x, _y, _z| {
-            gesture.set_state(gtk::EventSequenceState::Claimed);
-            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &2u32]);
+            if is_touch(gesture) { return; }
+            controller_inst().obj().emit_by_name::<()>("mouse-click", &[&this.id(), &2u32, &gesture.current_event_state().bits()]);
         }));
         gesture_right.set_button(gtk::gdk::ffi::GDK_BUTTON_SECONDARY as u32);
         self.obj().add_controller(&gesture_right);
 
+		// DISCRETE turns smooth touchpad scrolling into steps, so a wheel notch is one command either way
+		let scroll_handler = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL | gtk::EventControllerScrollFlags::DISCRETE);
+		scroll_handler.connect_scroll(clone!(@weak this => @default-return gtk::Inhibit(false), move |ctlr, _dx, dy| {
+			if dy != 0.0 {
+				let mods = ctlr.current_event_state().bits();
+				controller_inst().obj().emit_by_name::<()>("mouse-wheel", &[&this.id(), &if dy < 0.0 { 0u32 } else { 1u32 }, &mods]);
+			}
+			gtk::Inhibit(true)
+		}));
+        self.obj().add_controller(&scroll_handler);
+
+		// drags are reported whenever the pointer moves into another column. The gesture is on the playing area so
+		// its coordinates line up with the cells. Nothing is reported until the pointer has moved a cell's width, so a
+		// click that wobbles a little is still only a click.
+		let drag = gtk::GestureDrag::new();
+		drag.set_button(0);
+		let last_column = Rc::new(Cell::new(-1i32));
+		let dragging = Rc::new(Cell::new(false));
+		drag.connect_drag_begin(clone!(@strong last_column, @strong dragging => move |_gesture, _x, _y| {
+			last_column.set(-1);
+			dragging.set(false);
+		}));
+		drag.connect_drag_update(clone!(@weak this, @strong last_column, @strong dragging => move |gesture, dx, _dy| {
+			if is_touch(gesture) { return; }
+			if !dragging.get() {
+				if dx.abs() < this.cell_width() { return; }
+				dragging.set(true);
+			}
+			let Some((x, _y)) = gesture.start_point() else { return; };
+			let column = this.column_at(x + dx);
+			if column == last_column.get() { return; }
+			last_column.set(column);
+			let mods = gesture.current_event_state().bits();
+			// button 0 is a touch, which has gestures of its own
+			let Some(button) = gesture.current_button().checked_sub(1) else { return; };
+			controller_inst().obj().emit_by_name::<()>("mouse-drag", &[&this.id(), &button, &(column as u32), &mods]);
+		}));
+		self.playing_area.add_controller(&drag);
+
+		// Touch. GTK would also pass touches to the mouse handlers above as button 1, so those skip them. Without a
+		// touchscreen, GTK_DEBUG=touchscreen makes the mouse act as one.
+		let swipe = gtk::GestureDrag::new();
+		swipe.set_touch_only(true);
+		let two_fingers = Rc::new(Cell::new(false));
+		swipe.connect_drag_begin(clone!(@strong two_fingers => move |_gesture, _x, _y| two_fingers.set(false)));
+		swipe.connect_drag_end(clone!(@weak this, @strong two_fingers => move |_gesture, dx, dy| {
+			if two_fingers.get() { return; }
+			let (touch, count) = this.classify_touch(dx, dy);
+			controller_inst().obj().emit_by_name::<()>("touch", &[&this.id(), &touch, &count]);
+		}));
+		self.playing_area.add_controller(&swipe);
+
+		// a second finger starts a zoom. If it ends quickly without the fingers moving apart it was a two finger tap.
+		let zoom = gtk::GestureZoom::new();
+		let zoom_start = Rc::new(Cell::new(0i64));
+		zoom.connect_begin(clone!(@strong two_fingers, @strong zoom_start => move |_gesture, _sequence| {
+			two_fingers.set(true);
+			zoom_start.set(glib::monotonic_time());
+		}));
+		zoom.connect_end(clone!(@weak this, @strong zoom_start => move |gesture, _sequence| {
+			let quick = glib::monotonic_time() - zoom_start.get() < TAP_MICROSECONDS;
+			if quick && (gesture.scale_delta() - 1.0).abs() < 0.15 {
+				controller_inst().obj().emit_by_name::<()>("touch", &[&this.id(), &TOUCH_TWO_FINGER_TAP, &1u32]);
+			}
+		}));
+		self.playing_area.add_controller(&zoom);
+
 		let enter_handler = EventControllerMotion::new();
 		enter_handler.connect_enter(clone!(@weak this => move |_w, _x, _y, | controller_inst().obj().emit_by_name::<()>("select", &[&this.id()])));
         self.obj().add_controller(&enter_handler);
@@ -134,6 +206,9 @@ impl ObjectImpl for Board {
     }
 }
 
+// Pointer events have no sequence, touches do
+fn is_touch<G: IsA<gtk::GestureSingle>>(gesture: &G) -> bool { gesture.current_sequence().is_some() }
+
 impl WidgetImpl for Board {}
 impl BoxImpl for Board {}
 
@@ -155,6 +230,19 @@ pub const CMD_DROP: u32             = 8;
 // control commands
 pub const CMD_START: u32            = 9;
 pub const CMD_STOP: u32             = 10;
+// move the piece to a column, which is added to this. Columns count from the left of the screen.
+pub const CMD_COLUMN: u32           = 0x40000000;
+pub const CMD_COLUMN_END: u32       = 0x40000100;
+// touch gestures, indexes into keymap::TOUCH_NAMES
+pub const TOUCH_TAP: u32            = 0;
+pub const TOUCH_SWIPE_LEFT: u32     = 1;
+pub const TOUCH_SWIPE_RIGHT: u32    = 2;
+pub const TOUCH_SWIPE_DOWN: u32     = 3;
+pub const TOUCH_SWIPE_UP: u32       = 4;
+pub const TOUCH_TWO_FINGER_TAP: u32 = 5;
+// a touch that moves less than this is a tap, and a two finger tap is shorter than this
+const TAP_PIXELS: f64 = 12.0;
+const TAP_MICROSECONDS: i64 = 300_000;
 // location for cheat codes
 pub const CMD_CHEAT: u32            = 0x80000000;
 pub const CMD_CHEAT_END: u32        = 0x80000100;
@@ -183,7 +271,13 @@ pub struct Piece {
 	pos: u32,
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+// In a linked field the boards make one wide field and pieces can move from one board into the next. Lines are
+// either completed on each board separately or only when the row is full across every board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineRule {Board, Field, }
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum Orientation {#[default] North, East, South, West, }
 
 impl Orientation {
@@ -241,14 +335,88 @@ impl Piece {
     fn random() -> &'static Piece {
         &PIECES[fastrand::usize(0..PIECES.len())]
     }
+
+    fn named(name: &str) -> Option<&'static Piece> { PIECES.iter().find(|p| p.name == name) }
+}
+
+// the piece names in POS order, which is the order the summary counts them
+pub fn piece_names() -> Vec<&'static str> { PIECES.iter().map(|p| p.name).collect() }
+
+// Everything about a board needed to carry on a saved game, see Board::snapshot(). The cells are the colours on the
+// screen, one string per row from the top with each cell being the number of the piece there (its POS) or '.' for
+// empty. They include the falling piece.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BoardState {
+    pub Piece:       String,
+    pub Next:        String,
+    pub Orientation: Orientation,
+    pub Position:    (i32, i32),
+    pub Points:      u32,
+    pub Lines:       u32,
+    pub Dropping:    bool,
+    pub Bitmap:      Vec<u32>,
+    pub Cells:       Vec<String>,
+}
+
+impl BoardState {
+    // Makes sure the state fits board ID of BOARDS, each WIDTH by HEIGHT, before anything is set from it. The falling
+    // piece has to be where a game could have put it, or can_move() and friends would index past the bitmap.
+    pub fn check(&self, id: u32, boards: u32, width: u32, height: u32, linked: bool) -> Result<(), String> {
+        let piece = Piece::named(&self.Piece).ok_or(format!("board {}: unknown piece `{}`", id + 1, self.Piece))?;
+        Piece::named(&self.Next).ok_or(format!("board {}: unknown piece `{}`", id + 1, self.Next))?;
+        if self.Bitmap.len() != (height + 4) as usize || self.Cells.len() != height as usize
+            || self.Cells.iter().any(|row| row.chars().count() != width as usize) {
+            return Err(format!("board {}: saved board is not {}x{}", id + 1, width, height));
+        }
+        let (x, y) = self.Position;
+        let (width, height) = (width as i32, height as i32);
+        let cells = Board::mask_cells(piece.mask(self.Orientation), self.Position);
+        let on_board = if linked {
+            // columns anywhere on the field, see to_field()
+            let field = width * boards as i32;
+            cells.iter().all(|(cx, _)| (0..field).contains(&(id as i32 * width + width - 1 - cx)))
+        } else {
+            (-2..width).contains(&x) && cells.iter().all(|(cx, _)| (0..width).contains(cx))
+        };
+        if !on_board || !(-2..=height - 2).contains(&y) {
+            return Err(format!("board {}: the piece at ({}, {}) is off the board", id + 1, x, y));
+        }
+        // the walls and floor prepare() builds, which keep can_move() from looking past the sides
+        let walls = !(((1u32 << width) - 1) << 2);
+        for (row, bits) in self.Bitmap.iter().enumerate() {
+            let solid = row as i32 >= height + 2;
+            if (solid && *bits != u32::MAX) || (!solid && bits & walls != walls) {
+                return Err(format!("board {}: the saved bitmap has no walls in row {}", id + 1, row));
+            }
+        }
+        // every stacked cell is coloured. A coloured cell that isn't stacked has to be a falling piece, which on a linked
+        // field can belong to another board.
+        for (row, line) in self.Cells.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                if c != '.' && c.to_digit(10).and_then(|pos| PIECES.get(pos as usize)).is_none() {
+                    return Err(format!("board {}: unknown cell `{}`", id + 1, c));
+                }
+                let x = width - 1 - column as i32;
+                let stacked = self.Bitmap[row + 2] & (1 << (x + 2)) != 0;
+                let falling = linked || cells.contains(&(x, row as i32));
+                if stacked != (c != '.') && (stacked || !falling) {
+                    return Err(format!("board {}: the cells don't match the bitmap in row {}", id + 1, row + 1));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 
+
 impl Board {
     pub fn height(&self) -> u32 { *self.height_oc.get().unwrap() }
     pub fn width(&self) -> u32 { *self.width_oc.get().unwrap() }
     fn id(&self) -> u32 { *self.id_oc.get().unwrap() }
     pub fn show_preview(&self) -> bool { *self.show_preview_oc.get().unwrap() }
+    pub fn linked(&self) -> Option<LineRule> { *self.linked_oc.get().unwrap_or(&None) }
     
     // Most initializes correctly by default, BITMAP relies on height and width
     pub fn prepare(&self) {
@@ -301,6 +469,8 @@ impl Board {
     }
 
     pub fn do_command(&self, bits: u32) {
+        // everything the board does goes through here, including the timer's ticks, so this is what a replay needs
+        crate::controller::imp::record(self.id(), bits);
         match bits {
             CMD_LEFT => self.translate_piece(1, 0),
             CMD_RIGHT => self.translate_piece(-1, 0),
@@ -314,12 +484,15 @@ impl Board {
 			CMD_STOP => {self.internal.borrow().timer.stop(); true},
 			CMD_DROP => self.drop_piece(),
             CMD_CHEAT..=CMD_CHEAT_END => self.do_cheat(bits & 0xfff),
+            CMD_COLUMN..=CMD_COLUMN_END => self.follow_column((bits & 0xfff) as i32),
             _ => true,
         };
     }
 	
+    // a piece that was dropping when the game stopped carries on at the dropping speed
     fn start(&self) -> bool{
-		let delay = { self.delay(false) };
+		let dropping = self.internal.borrow().state & SS_DROPPING != 0;
+		let delay = { self.delay(dropping) };
 		let mut internal = self.internal.borrow_mut();
 		internal.timer = Timer::new(self.id(), delay, self.height());
 		internal.timer.start();
@@ -341,6 +514,43 @@ impl Board {
         true
     }
         
+    // Moves the piece a step at a time until its middle is in COLUMN, or it is blocked
+    fn follow_column(&self, column: i32) -> bool {
+        let target = self.width() as i32 - 1 - column;
+        let middle = {
+            let internal = self.internal.borrow();
+            let cells = Board::mask_cells(internal.piece.0.mask(internal.orientation), internal.xy);
+            let (min, max) = cells.iter().fold((i32::MAX, i32::MIN), |(min, max), (x, _)| (min.min(*x), max.max(*x)));
+            (min + max) / 2
+        };
+        let step = (target - middle).signum();
+        for _ in 0..(target - middle).abs() {
+            if !self.translate_piece(step, 0) { return false; }
+        }
+        true
+    }
+
+    fn cell_width(&self) -> f64 { (self.playing_area.width() as f64 / self.width() as f64).max(1.0) }
+
+    // Which touch gesture a finger moving (DX, DY) made, and for a sideways swipe how many columns it crossed
+    fn classify_touch(&self, dx: f64, dy: f64) -> (u32, u32) {
+        let cell_width = self.cell_width();
+        if dx.abs() < TAP_PIXELS && dy.abs() < TAP_PIXELS { (TOUCH_TAP, 1) }
+        else if dx.abs() > dy.abs() {
+            let columns = ((dx.abs() / cell_width).round() as u32).max(1);
+            (if dx < 0.0 { TOUCH_SWIPE_LEFT } else { TOUCH_SWIPE_RIGHT }, columns)
+        }
+        else if dy > 0.0 { (TOUCH_SWIPE_DOWN, 1) }
+        else { (TOUCH_SWIPE_UP, 1) }
+    }
+
+    // the screen column, from the left, at X pixels into the playing area
+    fn column_at(&self, x: f64) -> i32 {
+        let cell_width = self.playing_area.width() as f64 / self.width() as f64;
+        if cell_width <= 0.0 { return 0; }
+        ((x / cell_width) as i32).clamp(0, self.width() as i32 - 1)
+    }
+
     fn rotate_piece(&self, rotate: u32) -> bool{
         let orientation;
         {
@@ -355,7 +565,8 @@ impl Board {
 
     fn delay(&self, dropping: bool) -> u32 {
 		let lines = self.internal.borrow().score.1;
-		let msecs: u32 = (STARTING_TICK_MS as i32 as f64 * f64::powf(SPEEDUP_RATIO, (lines / LINES_BETWEEN_SPEEDUPS).into())) as u32;
+		let starting = match crate::controller::imp::starting_delay_ms() { 0 => STARTING_TICK_MS, ms => ms };
+		let msecs: u32 = (starting as i32 as f64 * f64::powf(SPEEDUP_RATIO, (lines / LINES_BETWEEN_SPEEDUPS).into())) as u32;
 		if dropping { (msecs as i32 as f64 * DROP_RATIO) as u32} else { msecs }
 	}
 	
@@ -403,20 +614,35 @@ impl Board {
     
     fn update_score(&self) {
         let (lines, bonus) = self.completed_lines();
-        let mut internal = self.internal.borrow_mut();
-        let delta_score = internal.piece.0.points(internal.orientation) + bonus;
-		let piece_num = internal.piece.0.pos;
-        internal.score.0 += delta_score;
-        internal.score.1 += lines;
-        self.points.set_label(&internal.score.0.to_string());
-        self.lines.set_label(&internal.score.1.to_string());
+        let (delta_score, piece_num);
+        {
+            let mut internal = self.internal.borrow_mut();
+            delta_score = internal.piece.0.points(internal.orientation) + bonus;
+            piece_num = internal.piece.0.pos;
+            internal.score.0 += delta_score;
+            internal.score.1 += lines;
+            self.points.set_label(&internal.score.0.to_string());
+            self.lines.set_label(&internal.score.1.to_string());
+        }
+        // the borrow must be released first, the controller may look at this board while handling the report
         controller_inst().obj().emit_by_name::<()>("board-report", &[&self.id(), &delta_score, &lines, &piece_num]);
     }
 
+    // number of rows from the bottom up to and including the highest occupied cell
+    pub fn stack_height(&self) -> u32 {
+        let bitmap = &self.internal.borrow().bitmap;
+        let mask: u32 = ((1 << self.width()) - 1) << 2;
+        for row in 0..self.height() {
+            if bitmap[(row + 2) as usize] & mask != 0 { return self.height() - row; }
+        }
+        0
+    }
+
     // see note above about different coordinate systems. Here is where they crash together.
     // BITMAP has padding of 2 bits on left, right, and bottom to make sure the mask always
     // is fully contained in the bitmap
     fn can_move(&self, mut mask: u16, xy: (i32, i32)) -> bool {
+		if self.linked().is_some() { return self.can_move_linked(mask, xy); }
 		if xy.1 > 0xffff {return false; }    // LOSE
         let bitmap = &self.internal.borrow().bitmap;
         let mut row: usize = (xy.1 + 2) as usize;
@@ -430,57 +656,87 @@ impl Board {
     }
 	
     fn completed_lines(&self) -> (u32, u32) {
-		// first compute the rows to remove
-        let mut to_remove = Vec::<i32>::new();    // this holds the line numbers to remove in bitmap coordinates, in ascending order
-		{
-			let bitmap: &Vec<u32> = &self.internal.borrow().bitmap;
-			// originally I used -1 here, as it is simpler. By making this mask I can use the leading bits to mark buffer rows for debugging
-			let mask: u32 = (0x1 << (self.width() + 4)) - 1;
-			for i in 2..(bitmap.len() as i32) - 2 {
-//              if bitmap[i as usize] == 0xffffffff {
-				if bitmap[i as usize] & mask == mask {
-					to_remove.push(i - 2);
-				} 
-			}
+		let len = match self.linked() {
+			None => {
+				let to_remove = self.full_rows();
+				self.remove_rows(&to_remove);
+				to_remove.len() as u32
+			},
+			Some(rule) => self.completed_field_lines(rule),
+		};
+		(len, len*len*5)    // (lines completed, completion bonus): bonus is 5 times completed lines squared (max of 100 pts)
+    }
+
+    // the line numbers to remove in board coordinates, in ascending order
+    fn full_rows(&self) -> Vec<i32> {
+        let mut to_remove = Vec::<i32>::new();
+		let bitmap: &Vec<u32> = &self.internal.borrow().bitmap;
+		// originally I used -1 here, as it is simpler. By making this mask I can use the leading bits to mark buffer rows for debugging
+		let mask: u32 = (0x1 << (self.width() + 4)) - 1;
+		for i in 2..(bitmap.len() as i32) - 2 {
+//          if bitmap[i as usize] == 0xffffffff {
+			if bitmap[i as usize] & mask == mask {
+				to_remove.push(i - 2);
+			} 
 		}
-		// next redraw the board with the lines removed. This must be done top-to-bottom
+		to_remove
+	}
+
+    // In a linked field the other boards' falling pieces can be drawn over the cells that move down, so they are
+    // taken off while the rows collapse and put back afterwards. All lines count for the board whose piece landed.
+    fn completed_field_lines(&self, rule: LineRule) -> u32 {
+		let all: Vec<&Board> = (0..board_count()).map(|id| board(id).imp()).collect();
+		let others: Vec<&&Board> = all.iter().filter(|b| b.id() != self.id()).collect();
+		others.iter().for_each(|b| b.paint_piece(true));
+		let len = match rule {
+			LineRule::Board => all.iter().map(|b| {
+				let to_remove = b.full_rows();
+				b.remove_rows(&to_remove);
+				to_remove.len() as u32
+			}).sum(),
+			LineRule::Field => {
+				let mut to_remove = self.full_rows();
+				for b in all.iter() {
+					let rows = b.full_rows();
+					to_remove.retain(|row| rows.contains(row));
+				}
+				all.iter().for_each(|b| b.remove_rows(&to_remove));
+				to_remove.len() as u32
+			},
+		};
+		others.iter().for_each(|b| {
+			b.settle();
+			b.paint_piece(false);
+		});
+		len
+	}
+
+    fn remove_rows(&self, to_remove: &[i32]) {
+		// first redraw the board with the lines removed. This must be done top-to-bottom
         to_remove.iter().for_each(|x| self.remove_row(*x));
 
 		// finally update the bitmap. This must be done bottom-to-top to maintain the offsets, and then add the new empty rows on top
 		let bitmap = &mut self.internal.borrow_mut().bitmap;
 		//let new_row_mask:u32 = 0xffffffff;
 		let new_row_mask:u32 = !(((1 << self.width()) - 1) << 2);  // mask is -1 with the bits representing the playing area cleared
-		for board_row in &to_remove {
+		for board_row in to_remove {
 			// working from top down, delete a row and replace it with a blank one on top
 			// +2: move to bitmap coords
 			bitmap.remove((board_row + 2) as usize);
 			bitmap.insert(0, new_row_mask);
 		}
-		let len: u32 = to_remove.len() as u32;
-		(len, len*len*5)    // (lines completed, completion bonus): bonus is 5 times completed lines squared (max of 100 pts)
     }
     
-    fn remove_row(&self, row: i32) {
-        // move down all cells above this row. Row is in board coords
-		for y in (0..row + 1).rev() {
-            for x in 0..self.width() as i32 {
-                let upper_color = self.get_cell_color(x, y - 1);
-                if upper_color != self.get_cell_color(x, y) {
-                    self.set_cell_color((x, y), &upper_color);
-                }
-            }
-        }
-    }
+    // move down all cells above this row. Row is in board coords
+    fn remove_row(&self, row: i32) { self.playing_area.imp().remove_row(row); }
     
     // All pieces in their North position are contained in rows 1 and 2, so the mask is of the form 0x0**0 and only the
     // middle 2 quartets are drawn
     fn draw_preview(&self) {
         let internal = self.internal.borrow();
         let mut mask = internal.piece.1.mask(Orientation::North) >> 4;
-        let piece_type = &[internal.piece.1.name];
-        let empty = &["empty"];
         for i in 0..8 {
-            self.preview.child_at(3 - i%4, i/4).unwrap().set_css_classes( if mask & 1 > 0 {piece_type} else {empty});
+            self.preview.imp().set_color(3 - i%4, i/4, if mask & 1 > 0 {internal.piece.1.name} else {"empty"});
             mask >>= 1;
         }
     }
@@ -534,6 +790,7 @@ impl Board {
     }
     
     fn add_piece_to_bitmap(&self) -> bool {
+		if self.linked().is_some() { return self.add_piece_to_field(); }
         let mut internal = self.internal.borrow_mut();
 		if internal.xy.1 < 0 {return false; }    // LOSE
         let mut mask = internal.piece.0.mask(internal.orientation) as u32;
@@ -547,27 +804,159 @@ impl Board {
     }
     
     // lowest level functions
-    // Drawing the pieces is done by setting css class for widgets in the board.playing_area object. This way I don't need
-    // to handle any redrawing, and setting the colors is simple. If drawing turns out to be better then board.playing_area
-    // needs to be replaced with a drawing area and these functions need to be redone
-    fn cell_at(&self, xy: (i32, i32)) -> Option<gtk::Widget> {
-        self.playing_area.child_at((self.width() as i32) - 1 - xy.0, xy.1)
-    }
-
-    fn get_cell_color(&self, x: i32, y: i32) -> String {
-        let cell_opt = self.cell_at((x, y));
-        match cell_opt {
-            None => "empty".to_string(),
-            Some(widget) => { let class_names = widget.css_classes();
-                              if !class_names.is_empty() {class_names[0].to_string()} else {"empty".to_string()}
+    // Drawing the pieces is done by setting the colour of cells in board.playing_area, which draws them with the CSS
+    // class of the colour (see cell_grid.rs). Board x runs right to left, the grid's columns left to right.
+    fn set_cell_color(&self, xy: (i32, i32), piece_name: &str) {
+		let on_board = self.playing_area.imp().set_color((self.width() as i32) - 1 - xy.0, xy.1, piece_name);
+		// in a linked field cells past the sides belong to the neighbouring boards
+		if !on_board && self.linked().is_some() && (xy.0 < 0 || xy.0 >= self.width() as i32) {
+			if let Some((id, x)) = self.field_to_board(self.to_field(xy.0)) {
+				self.field_board(id).set_cell_color((x, xy.1), piece_name);
+			}
+		}
+    }
+
+    //////////////////////////////////////////////////////////////////
+    //
+    // Linked field
+    //
+    // Field columns count left to right across all the boards, rows are the same as on a single board. Board x runs
+    // right to left, so converting flips it, as set_cell_color() does for the grid. All boards have the same width.
+    //
+    //////////////////////////////////////////////////////////////////
+    fn to_field(&self, x: i32) -> i32 { (self.id() * self.width()) as i32 + self.width() as i32 - 1 - x }
+
+    // the board and its x for a field column, or None if it is off the field
+    fn field_to_board(&self, column: i32) -> Option<(u32, i32)> {
+        let width = self.width() as i32;
+        let count = board_count().max(self.id() as usize + 1);
+        if column < 0 || column >= width * count as i32 { return None; }
+        Some(((column / width) as u32, width - 1 - column % width))
+    }
+
+    // while the boards are being made this board is not in BOARDS yet, but its first piece still needs checking
+    fn field_board(&self, id: u32) -> &Board {
+        if id == self.id() { self } else { board(id as usize).imp() }
+    }
+
+    // the board coordinates of the cells of MASK placed at XY
+    fn mask_cells(mut mask: u16, xy: (i32, i32)) -> Vec<(i32, i32)> {
+        let mut cells = Vec::<(i32, i32)>::new();
+        let mut i = 0;
+        while mask != 0 {
+            if mask & 1 == 1 { cells.push((xy.0 + i % 4, xy.1 + i / 4)); }
+            mask >>= 1;
+            i += 1;
+        }
+        cells
+    }
+
+    // cells above the top of the board are open, the sides and bottom are not
+    fn cell_occupied(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.width() as i32 || y + 2 < 0 { return true; }
+        match self.internal.borrow().bitmap.get((y + 2) as usize) {
+            Some(row) => row & (1 << (x + 2)) != 0,
+            None => true,
+        }
+    }
+
+    fn piece_field_cells(&self) -> Vec<(i32, i32)> {
+        let internal = self.internal.borrow();
+        Board::mask_cells(internal.piece.0.mask(internal.orientation), internal.xy).iter().map(|(x, y)| (self.to_field(*x), *y)).collect()
+    }
+
+    // a piece can't overlap the stacks on any board or the other boards' falling pieces
+    fn can_move_linked(&self, mask: u16, xy: (i32, i32)) -> bool {
+        Board::mask_cells(mask, xy).iter().all(|(x, y)| {
+            let column = self.to_field(*x);
+            match self.field_to_board(column) {
+                None => false,
+                Some((id, local_x)) => {
+                    !self.field_board(id).cell_occupied(local_x, *y) &&
+                        (0..board_count()).map(|other| board(other).imp())
+                        .filter(|other| other.id() != self.id())
+                        .all(|other| !other.piece_field_cells().contains(&(column, *y)))
+                },
+            }
+        })
+    }
+
+    fn add_piece_to_field(&self) -> bool {
+        let cells = {
+            let internal = self.internal.borrow();
+            if internal.xy.1 < 0 {return false; }    // LOSE
+            Board::mask_cells(internal.piece.0.mask(internal.orientation), internal.xy)
+        };
+        for (x, y) in cells {
+            if let Some((id, local_x)) = self.field_to_board(self.to_field(x)) {
+                self.field_board(id).internal.borrow_mut().bitmap[(y + 2) as usize] |= 1 << (local_x + 2);
             }
         }
+        true
     }
-    
-    fn set_cell_color(&self, xy: (i32, i32), piece_name: &str) {
-		if let Some(cell) = self.cell_at(xy) { cell.set_css_classes(&[piece_name]); }
+
+    fn paint_piece(&self, erase: bool) {
+        let (cells, name) = {
+            let internal = self.internal.borrow();
+            (Board::mask_cells(internal.piece.0.mask(internal.orientation), internal.xy), internal.piece.0.name)
+        };
+        cells.iter().for_each(|xy| self.set_cell_color(*xy, if erase { "empty" } else { name }));
     }
 
+    // when rows collapse under a falling piece the stack can move into it, so move it up until it fits again
+    fn settle(&self) {
+        let (mask, mut xy) = {
+            let internal = self.internal.borrow();
+            (internal.piece.0.mask(internal.orientation), internal.xy)
+        };
+        while xy.1 > -2 && !self.can_move(mask, xy) { xy.1 -= 1; }
+        self.internal.borrow_mut().xy = xy;
+    }
+
+
+    pub fn snapshot(&self) -> BoardState {
+        let cells = (0..self.height() as i32).map(|row| {
+            (0..self.width() as i32).map(|column| {
+                match self.playing_area.imp().color(column, row).and_then(Piece::named) {
+                    Some(piece) => char::from_digit(piece.pos, 10).unwrap_or('.'),
+                    None => '.',
+                }
+            }).collect()
+        }).collect();
+        let internal = self.internal.borrow();
+        BoardState { Piece: internal.piece.0.name.to_string(), Next: internal.piece.1.name.to_string(), Orientation: internal.orientation,
+                     Position: internal.xy, Points: internal.score.0, Lines: internal.score.1, Dropping: internal.state & SS_DROPPING != 0,
+                     Bitmap: internal.bitmap.clone(), Cells: cells, }
+    }
+
+    // Puts the board back the way snapshot() found it. The timer is left stopped, CMD_START gets it going again.
+    pub fn restore(&self, state: &BoardState) -> Result<(), String> {
+        state.check(self.id(), board_count().max(self.id() as usize + 1) as u32, self.width(), self.height(), self.linked().is_some())?;
+        let (Some(piece), Some(next)) = (Piece::named(&state.Piece), Piece::named(&state.Next)) else { return Err("unknown piece".to_string()); };
+        for (row, line) in state.Cells.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let name = c.to_digit(10).and_then(|pos| PIECES.get(pos as usize)).map_or("empty", |p| p.name);
+                self.playing_area.imp().set_color(column as i32, row as i32, name);
+            }
+        }
+        {
+            let mut internal = self.internal.borrow_mut();
+            internal.timer.stop();
+            internal.piece = (piece, next);
+            internal.orientation = state.Orientation;
+            internal.xy = state.Position;
+            internal.score = (state.Points, state.Lines);
+            internal.bitmap = state.Bitmap.clone();
+            internal.state &= !(SS_NEW_PIECE | SS_DROPPING);
+            if state.Dropping { internal.state |= SS_DROPPING; }
+            self.points.set_label(&state.Points.to_string());
+            self.lines.set_label(&state.Lines.to_string());
+        }
+        let delay = self.delay(state.Dropping);
+        self.internal.borrow_mut().timer = Timer::new(self.id(), delay, self.height());
+        if self.show_preview() { self.draw_preview(); }
+        Ok(())
+    }
 
     // debugging function: set BITARRAY to reconstruct position
     fn init_bitmap_to(&self, array: &[u32]) {
@@ -605,6 +994,7 @@ impl Timer {
 
 	// be sure to stop the old timer when starting a new one
 	fn start(&self) {
+		if crate::controller::imp::playing_back() { return; }
 		let quit_count = Rc::clone(&self.quit_count);
 		let board_id = self.board_id as usize;
 		let f = move || -> glib::Continue {
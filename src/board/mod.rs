@@ -1,9 +1,10 @@
 pub mod imp;
+pub mod cell_grid;
 
 use gtk::{gio, glib};
 use gtk::glib::closure_local;
 use gtk::subclass::prelude::*;
-use gtk::prelude::{GridExt, WidgetExt, ObjectExt, BoxExt};
+use gtk::prelude::{WidgetExt, ObjectExt};
 
 glib::wrapper! {
     pub struct Board(ObjectSubclass<imp::Board>)
@@ -11,25 +12,18 @@ glib::wrapper! {
 }
 
 impl Board {
-    pub fn new (id: u32, width: u32, height: u32, preview: bool) -> Self {
+    pub fn new (id: u32, width: u32, height: u32, preview: bool, linked: Option<imp::LineRule>) -> Self {
         let board: Board = glib::Object::builder().build();
+        let _ = board.imp().linked_oc.set(linked);
         let _ = board.imp().width_oc.set(width);
         let _ = board.imp().height_oc.set(height);
         let _ = board.imp().show_preview_oc.set(preview);
         let _ = board.imp().id_oc.set(id);
         let this: &imp::Board = board.imp();
         this.obj().set_focusable(true);
-        for x in 0..width {
-            for y in 0..height {
-                this.playing_area.attach(&Board::make_cell(), x as i32, y as i32, 1, 1);
-            }
-        }
+        this.playing_area.imp().set_size(width, height);
         if preview {
-            for x in 0..4 {
-                for y in 0..2 {
-                    this.preview.attach(&Board::make_cell(), x, y, 1, 1);
-                }
-            }
+            this.preview.imp().set_size(4, 2);
         }
         board.connect_closure(
             "board-command",
@@ -41,19 +35,5 @@ impl Board {
         board.imp().prepare();
         board
     }
-
-    // helper function to make a single cell
-    fn make_cell() -> gtk::Box {
-        let cell = gtk::Box::builder()
-            .orientation(gtk::Orientation::Vertical)
-            .build();
-        let label = gtk::Label::builder()
-//            .label("")
-            .build();
-        label.add_css_class("cell");
-        //cell.add_css_class("cell");
-        cell.append(&label);
-        cell
-    }
 }
 
@@ -1,7 +1,11 @@
 
-use std::env;
-use clap::{Parser, value_parser};               // Command Line Argument Processing
+use clap::{Parser, CommandFactory, FromArgMatches, value_parser};               // Command Line Argument Processing
+use clap::parser::ValueSource;
 use serde::{Deserialize, Serialize};            // config file parsing
+use crate::controller::imp::FocusPolicy;
+use crate::board::imp::LineRule;
+use crate::controller::imp::keymap::{Keymap, KeyBindings, KeySections, DEFAULT_PROFILE};
+use std::collections::BTreeMap;
 
 //////////////////////////////////////////////////////////////////
 //
@@ -26,11 +30,19 @@ const BOARDS_DEFAULT:   u32 = 2;
 const WIDTH_DEFAULT:    u32 = 10;
 const HEIGHT_DEFAULT:   u32 = 20;
 const CELLSIZE_DEFAULT: u32 = 20;
-const DELAY_DEFAULT:    f64 = 0.05;
+pub const DELAY_DEFAULT: f64 = 0.5;   // seconds between pieces falling a row at the start, before the game speeds up
 //const EXTENDED_DEFAULT: f64 = 0.03;
 const PREVIEW_DEFAULT:  bool = true;
+const PLAYERS_DEFAULT:  u32 = 1;
+const AUTO_PAUSE_DEFAULT: bool = true;
+const GAMEPADS_DEFAULT: bool = true;
+const REPLAY_DIR_DEFAULT: &str = "~/.tetrii-replays";
+const SAVE_FILE_DEFAULT: &str = "~/.tetrii-saved-game";
+pub const DAS_DEFAULT:  u32 = 170;      // milliseconds before a held movement key starts repeating
+pub const ARR_DEFAULT:  u32 = 50;       // milliseconds between repeats
+pub const SOFT_DROP_DEFAULT: u32 = 30;      // milliseconds between repeats of a held Down
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about)]
 pub struct Config {
     #[clap(short, long, default_value_t = BOARDS_DEFAULT, value_parser=value_parser!(u32).range(0..5))]
@@ -51,6 +63,64 @@ pub struct Config {
     pub config_file: String,
     #[clap(short, long, default_value_t = String::from("style.css"))]
     pub style: String,
+    #[clap(short, long, value_enum, default_value_t = FocusPolicy::default())]
+    pub focus: FocusPolicy,
+    #[clap(short='P', long, default_value_t = PLAYERS_DEFAULT, value_parser=value_parser!(u32).range(1..5))]
+    pub players: u32,
+    #[clap(short, long="team")]
+    pub teams: Vec<Team>,
+    #[clap(short='L', long, value_enum)]
+    pub linked: Option<LineRule>,
+    #[clap(long, default_value_t = 0)]
+    pub ramp_lines: u32,
+    #[clap(long, default_value_t = 0)]
+    pub ramp_minutes: u32,
+    #[clap(short, long="keys", default_value_t = String::from(DEFAULT_PROFILE))]
+    pub key_profile: String,
+    // only set from the config file, the default profile is always first
+    #[clap(skip)]
+    pub keymaps: Vec<(String, Keymap)>,
+    #[clap(long, default_value_t = DAS_DEFAULT, value_parser=value_parser!(u32).range(0..1000))]
+    pub das: u32,
+    #[clap(long, default_value_t = ARR_DEFAULT, value_parser=value_parser!(u32).range(1..1000))]
+    pub arr: u32,
+    #[clap(long, default_value_t = SOFT_DROP_DEFAULT, value_parser=value_parser!(u32).range(1..1000))]
+    pub soft_drop: u32,
+    // pause when the controller window loses focus or is minimized
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = AUTO_PAUSE_DEFAULT)]
+    pub auto_pause: bool,
+    // look for gamepads in /dev/input, including ones plugged in later
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = GAMEPADS_DEFAULT)]
+    pub gamepads: bool,
+    // recorded pad event streams to play back as pads, see gamepad.rs
+    #[clap(long)]
+    pub pad_events: Vec<String>,
+    // finished games are saved here as replays, an empty name turns that off
+    #[clap(long, default_value_t = String::from(REPLAY_DIR_DEFAULT))]
+    pub replay_dir: String,
+    // Save game in the controller window writes here, and Load game reads it
+    #[clap(long, default_value_t = String::from(SAVE_FILE_DEFAULT))]
+    pub save_file: String,
+    // write the summary of each finished game to this file, as JSON if it ends in .json and CSV otherwise
+    #[clap(long)]
+    pub summary_out: Option<String>,
+    // show a saved replay instead of playing. Only from the command line.
+    #[clap(long)]
+    pub replay: Option<String>,
+    // a named set of values from the Profiles section of the config file, used in place of the top level ones
+    #[clap(long)]
+    pub profile: Option<String>,
+    // only set from the config file, the names of all the profiles in it
+    #[clap(skip)]
+    pub profiles: Vec<String>,
+    // everything wrong with the values, found while building the config. Bad values are replaced with the defaults.
+    #[clap(skip)]
+    pub problems: Vec<ConfigProblem>,
+    // the fields set on the command line and in the config file, to tell where a bad value came from
+    #[clap(skip)]
+    command_line: Vec<String>,
+    #[clap(skip)]
+    from_file: Vec<String>,
 //    #[clap(short, long, default_value_t = 999)]
 //    pub initial_piece: usize,
 }
@@ -62,21 +132,192 @@ pub struct Config {
 impl Config {
     pub fn build_config() -> Config {
         // command line arguments 
-        let mut config = Config::parse();
+        let mut config = Config::from_command_line();
         ConfigOptions::merge_into(&mut config);
+        Config::finish(config)
+    }
+
+    // Config::parse(), keeping note of which values were given. CLAP still exits for bad arguments, printing usage.
+    fn from_command_line() -> Config {
+        let matches = Config::command().get_matches();
+        let mut config = Config::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        config.command_line = matches.ids().filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string()).collect();
+        config
+    }
+
+    // The config as it would be started with profile NAME, which is used by the Options window to switch profiles. An
+    // empty NAME is the top level values without any profile.
+    pub fn with_profile(name: &str) -> Config {
+        let mut config = Config::from_command_line();
+        config.profile = Some(name.to_string());
+        ConfigOptions::merge_into(&mut config);
+        Config::finish(config)
+    }
+
+    // the checks and fixing up after the config file is merged in
+    fn finish(mut config: Config) -> Config {
+        if config.keymaps.first().is_none_or(|k| k.0 != DEFAULT_PROFILE) {
+            config.keymaps.insert(0, (DEFAULT_PROFILE.to_string(), Keymap::default()));
+        }
+        if !config.keymaps.iter().any(|k| k.0 == config.key_profile) {
+            let message = format!("No keymap profile named `{}`, using the default keys", config.key_profile);
+            config.problem("key_profile", message);
+            config.key_profile = DEFAULT_PROFILE.to_string();
+        }
+        // merge_into() clears it if the file has no such profile, but not when there is no file to read
+        if let Some(name) = config.profile.take() {
+            if config.profiles.contains(&name) { config.profile = Some(name); }
+            else if !name.is_empty() { config.problem("profile", format!("No profile named `{}`, using the top level values", name)); }
+        }
+        config.style = expand_filename(&config.style);
+        // the default one is allowed to be missing, the built in copy stands in for it
+        if config.source("style") != ConfigSource::Default && crate::options::imp::find_style(&config.style).is_none() {
+            let message = format!("Could not find stylesheet `{}`, using the built in one", config.style);
+            config.problem("style", message);
+        }
+        config.replay_dir = expand_filename(&config.replay_dir);
+        config.save_file = expand_filename(&config.save_file);
+        config.summary_out = config.summary_out.map(|file| expand_filename(&file));
         config.check_values();
         config
     }
 
+    // where the value of FIELD came from
+    fn source(&self, field: &str) -> ConfigSource {
+        if self.command_line.iter().any(|f| f == field) { ConfigSource::CommandLine }
+        else if self.from_file.iter().any(|f| f == field) { ConfigSource::File(expand_filename(&self.config_file)) }
+        else { ConfigSource::Default }
+    }
+
+    fn problem(&mut self, field: &str, message: String) {
+        let source = self.source(field);
+        self.problems.push(ConfigProblem { field: field.to_string(), source, message, });
+    }
+
+    // for problems with the file itself rather than a value in it
+    fn file_problem(&mut self, field: &str, message: String) {
+        let source = ConfigSource::File(expand_filename(&self.config_file));
+        self.problems.push(ConfigProblem { field: field.to_string(), source, message, });
+    }
+
+    // A config with a profile is saved in that profile, otherwise in the top level values
     pub fn save(&self, filename: &str) -> Result<String, String> {
-        ConfigOptions::from_config(self).save(filename)
+        match &self.profile {
+            Some(name) => ConfigOptions::from_config(self).save_profile(filename, name),
+            None => ConfigOptions::from_config(self).save(filename),
+        }
+    }
+
+    // CLAP coes check for these from the command line, but this checks config file as well. A bad value is noted
+    // in PROBLEMS and the default used instead, so the game can still start. Values put in afterwards, like a
+    // replay's, need checking again.
+    pub fn check_values(&mut self) {
+        if !(1..=5).contains(&self.boards) {
+            self.problem("boards", format!("Number of boards must be between 1 and 5, not {}", self.boards));
+            self.boards = BOARDS_DEFAULT;
+        }
+        if !(8..=28).contains(&self.width) {
+            self.problem("width", format!("Board width must be between 8 and 28, not {}", self.width));
+            self.width = WIDTH_DEFAULT;
+        }
+        if !(10..=40).contains(&self.height) {
+            self.problem("height", format!("Board height must be between 10 and 40, not {}", self.height));
+            self.height = HEIGHT_DEFAULT;
+        }
+        if !(10..=50).contains(&self.cell_size) {
+            self.problem("cell_size", format!("Cell size must be between 10 and 50 pixels, not {}", self.cell_size));
+            self.cell_size = CELLSIZE_DEFAULT;
+        }
+        if !(self.delay > 0.0 && self.delay < 10.0) {
+            self.problem("delay", format!("Delay must be more than 0 and less than 10 seconds, not {}", self.delay));
+            self.delay = DELAY_DEFAULT;
+        }
+        if !(1..=4).contains(&self.players) {
+            self.problem("players", format!("Number of players must be between 1 and 4, not {}", self.players));
+            self.players = PLAYERS_DEFAULT;
+        }
+        if self.das >= 1000 {
+            self.problem("das", format!("Auto-shift delay must be less than 1000 ms, not {}", self.das));
+            self.das = DAS_DEFAULT;
+        }
+        if !(1..1000).contains(&self.arr) {
+            self.problem("arr", format!("Auto-repeat rate must be between 1 and 999 ms, not {}", self.arr));
+            self.arr = ARR_DEFAULT;
+        }
+        if !(1..1000).contains(&self.soft_drop) {
+            self.problem("soft_drop", format!("Soft drop rate must be between 1 and 999 ms, not {}", self.soft_drop));
+            self.soft_drop = SOFT_DROP_DEFAULT;
+        }
+        // a team with a board the game doesn't have is dropped
+        let boards = self.boards;
+        let (teams, bad): (Vec<Team>, Vec<Team>) = std::mem::take(&mut self.teams).into_iter().partition(|t| t.boards.iter().all(|b| *b < boards));
+        self.teams = teams;
+        for team in bad {
+            self.problem("teams", format!("Team `{}` has a board past the {} in the game, ignoring it", team, boards));
+        }
     }
+}
 
-    // CLAP coes check for these from the command line, but this checks config file as well.
-    fn check_values(&self) {
-        assert!(1 <= self.boards && self.boards <= 5, "Number of boards must be between 1 and 5");
-        assert!(8 <= self.width && self.width <= 28, "Board width must be between 8 and 28");
-        assert!(10 <= self.height && self.height <= 40, "Board height must be between 10 and 40");
+// Where a config value came from, for reporting problems
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    CommandLine,
+    File(String),
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::CommandLine => write!(f, "command line"),
+            ConfigSource::File(name) => write!(f, "config file {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigProblem {
+    pub field: String,
+    pub source: ConfigSource,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.field, self.source, self.message)
+    }
+}
+
+// A team is a name and a set of boards which are scored together. On the command line and in the config file it is
+// written as NAME=BOARD,BOARD,... with boards counting from 1, internally the board ids count from 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Team {
+    pub name: String,
+    pub boards: Vec<u32>,
+}
+
+impl std::str::FromStr for Team {
+    type Err = String;
+    fn from_str(desc: &str) -> Result<Team, String> {
+        let (name, list) = desc.split_once('=').ok_or(format!("Team `{}` should be written as NAME=BOARD,BOARD,...", desc))?;
+        if name.trim().is_empty() { return Err(format!("Team `{}` has no name", desc)); }
+        let mut boards = Vec::<u32>::new();
+        for item in list.split(',') {
+            match item.trim().parse::<u32>() {
+                Ok(n) if (1..=5).contains(&n) => boards.push(n - 1),
+                _ => return Err(format!("Team `{}`: `{}` is not a board number between 1 and 5", desc, item.trim())),
+            }
+        }
+        Ok(Team { name: name.trim().to_string(), boards, })
+    }
+}
+
+impl std::fmt::Display for Team {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let boards: Vec<String> = self.boards.iter().map(|b| (b + 1).to_string()).collect();
+        write!(f, "{}={}", self.name, boards.join(","))
     }
 }
 
@@ -101,12 +342,35 @@ struct ConfigOptions {
     Preview:        Option<bool>,
 //    InitialPiece:   Option<usize>,
     Style:          Option<String>,
+    Focus:          Option<FocusPolicy>,
+    Players:        Option<u32>,
+    Teams:          Option<Vec<String>>,
+    Linked:         Option<LineRule>,
+    RampLines:      Option<u32>,
+    RampMinutes:    Option<u32>,
+    KeysReplace:    Option<bool>,
+    Keys:           Option<KeyBindings>,
+    PlayerKeys:     Option<Vec<KeyBindings>>,
+    KeyProfile:     Option<String>,
+    KeyProfiles:    Option<BTreeMap<String, KeySections>>,
+    Macros:         Option<BTreeMap<String, Vec<String>>>,
+    Das:            Option<u32>,
+    Arr:            Option<u32>,
+    SoftDrop:       Option<u32>,
+    AutoPause:      Option<bool>,
+    Gamepads:       Option<bool>,
+    PadEvents:      Option<Vec<String>>,
+    ReplayDir:      Option<String>,
+    SaveFile:       Option<String>,
+    Profile:        Option<String>,
+    Profiles:       Option<BTreeMap<String, ConfigOptions>>,
 }
 
 impl ConfigOptions {
     // creates a ConfigOptions from a Config. This is needed so that values written to the config file will
     // have the right names.
     fn from_config(config: &Config) -> ConfigOptions {
+        let default_keys = config.keymaps.iter().find(|k| k.0 == DEFAULT_PROFILE).map(|k| k.1.to_sections()).unwrap_or_default();
         ConfigOptions{ Boards:         Some(config.boards),
                        Width:          Some(config.width),
                        Height:         Some(config.height),
@@ -116,23 +380,96 @@ impl ConfigOptions {
                        Preview:        Some(config.preview),
 //                       InitialPiece:   Some(config.initial_piece),
                        Style:          Some(config.style.to_string()),
+                       Focus:          Some(config.focus),
+                       Players:        Some(config.players),
+                       Teams:          Some(config.teams.iter().map(|t| t.to_string()).collect()),
+                       Linked:         config.linked,
+                       RampLines:      Some(config.ramp_lines),
+                       RampMinutes:    Some(config.ramp_minutes),
+                       KeysReplace:    Some(true),
+                       Keys:           default_keys.Keys,
+                       PlayerKeys:     default_keys.PlayerKeys,
+                       Macros:         default_keys.Macros,
+                       KeyProfile:     Some(config.key_profile.clone()),
+                       KeyProfiles:    Some(config.keymaps.iter().filter(|k| k.0 != DEFAULT_PROFILE)
+                                            .map(|(name, keymap)| (name.clone(), keymap.to_sections())).collect()),
+                       Das:            Some(config.das),
+                       Arr:            Some(config.arr),
+                       SoftDrop:       Some(config.soft_drop),
+                       AutoPause:      Some(config.auto_pause),
+                       Gamepads:       Some(config.gamepads),
+                       PadEvents:      Some(config.pad_events.clone()),
+                       ReplayDir:      Some(config.replay_dir.clone()),
+                       SaveFile:       Some(config.save_file.clone()),
+                       Profile:        config.profile.clone(),
+                       Profiles:       None,
+        }
+    }
+
+    // Puts the values set in PROFILE in place of these. The keymaps are shared by all the profiles, so a profile only
+    // picks one with KeyProfile, and a profile can't have profiles of its own.
+    fn overlay(&mut self, profile: ConfigOptions) {
+        self.Boards      = profile.Boards.or(self.Boards);
+        self.Width       = profile.Width.or(self.Width);
+        self.Height      = profile.Height.or(self.Height);
+        self.CellSize    = profile.CellSize.or(self.CellSize);
+        self.Delay       = profile.Delay.or(self.Delay);
+        self.Preview     = profile.Preview.or(self.Preview);
+        self.Style       = profile.Style.or(self.Style.take());
+        self.Focus       = profile.Focus.or(self.Focus);
+        self.Players     = profile.Players.or(self.Players);
+        self.Teams       = profile.Teams.or(self.Teams.take());
+        self.Linked      = profile.Linked.or(self.Linked);
+        self.RampLines   = profile.RampLines.or(self.RampLines);
+        self.RampMinutes = profile.RampMinutes.or(self.RampMinutes);
+        self.KeyProfile  = profile.KeyProfile.or(self.KeyProfile.take());
+        self.Das         = profile.Das.or(self.Das);
+        self.Arr         = profile.Arr.or(self.Arr);
+        self.SoftDrop    = profile.SoftDrop.or(self.SoftDrop);
+        self.AutoPause   = profile.AutoPause.or(self.AutoPause);
+        self.Gamepads    = profile.Gamepads.or(self.Gamepads);
+        self.PadEvents   = profile.PadEvents.or(self.PadEvents.take());
+        self.ReplayDir   = profile.ReplayDir.or(self.ReplayDir.take());
+        self.SaveFile    = profile.SaveFile.or(self.SaveFile.take());
+    }
+
+    fn to_mapping(&self, filename: &str) -> Result<serde_yaml::Mapping, String> {
+        match serde_yaml::to_value(self) {
+            Ok(serde_yaml::Value::Mapping(entries)) => Ok(entries),
+            _ => Err("Could not convert the config for ".to_string() + filename),
         }
     }
 
-    // dumps a configuration to a file in yaml format
+    // writes a configuration to a file in yaml format, keeping whatever else is in the file (see write_entries()).
+    // The profiles in the file are left alone.
     fn save(&self, filename: &str) -> Result<String, String> {
         let expanded_name = expand_filename(filename);
-        let file = match std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&expanded_name) {
-                Ok(f) => f,
-                Err(err) => { return Err("Failed opening config file for write: ".to_string() + &expanded_name + ": " + &err.to_string()); },
-            };
-        match serde_yaml::to_writer(file, self) {
-            Ok(_) => Ok("Wrote config file ".to_string() + &expanded_name),
-            Err(err) => Err("Error writing config file ".to_string() + &expanded_name + ": " + &err.to_string())
+        let mut entries = self.to_mapping(&expanded_name)?;
+        entries.remove("Profiles");
+        write_entries(&expanded_name, &entries)?;
+        Ok("Wrote config file ".to_string() + &expanded_name)
+    }
+
+    // writes the values into profile NAME, which also becomes the one used at startup. The keymaps go in the top
+    // level as usual.
+    fn save_profile(&self, filename: &str, name: &str) -> Result<String, String> {
+        let expanded_name = expand_filename(filename);
+        let mut entries = self.to_mapping(&expanded_name)?;
+        let mut profile = serde_yaml::Mapping::new();
+        for key in ["KeysReplace", "Keys", "PlayerKeys", "Macros", "KeyProfiles", "Profile", "Profiles"] {
+            if let Some(value) = entries.remove(key) { profile.insert(serde_yaml::Value::from(key), value); }
         }
+        // ENTRIES now has the profile's values and PROFILE the top level ones, swap them over
+        std::mem::swap(&mut entries, &mut profile);
+        profile.retain(|_, value| !value.is_null());
+        let mut profiles = match read_mapping(&expanded_name)?.remove("Profiles") {
+            Some(serde_yaml::Value::Mapping(profiles)) => profiles,
+            _ => serde_yaml::Mapping::new(),
+        };
+        profiles.insert(serde_yaml::Value::from(name), serde_yaml::Value::Mapping(profile));
+        entries.insert(serde_yaml::Value::from("Profiles"), serde_yaml::Value::Mapping(profiles));
+        write_entries(&expanded_name, &entries)?;
+        Ok("Wrote profile `".to_string() + name + "` to config file " + &expanded_name)
     }
 
     // Here the config structure is made from combining the command line arguments and the default values.
@@ -143,12 +480,13 @@ impl ConfigOptions {
         let mut config_file = expand_filename(&config.config_file);
         
         if !config_file.is_empty() {
-            // read file as String
+            // read file as String. Not having the default config file is fine, most people won't.
             let read_result = std::fs::read_to_string(&mut config_file);
             let read_string = match read_result {
                 Ok(string) => string,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound && config.source("config_file") == ConfigSource::Default => return,
                 Err(err) => {
-                    eprintln!("Could not open config file `{}`: {}\nproceeding without it", &config_file, err);
+                    config.file_problem("config_file", format!("Could not open config file: {}, proceeding without it", err));
                     return;
                 },
             };
@@ -156,49 +494,248 @@ impl ConfigOptions {
             let mut yaml_options = match yaml_result {
                 Ok(opts) => opts,
                 Err(err) => {
-                    eprintln!("Error parsing config file {}: {}\n   Ignoring config file", &config_file, err);
+                    config.file_problem("config_file", format!("Error parsing config file: {}, ignoring it", err));
                     return;
                 },
             };
+            // A profile replaces the top level values it sets. One named on the command line (or picked in the Options
+            // window) is used before the one named in the file, and an empty name means none.
+            let profiles = yaml_options.Profiles.take().unwrap_or_default();
+            config.profiles = profiles.keys().cloned().collect();
+            let profile = config.profile.clone().or(yaml_options.Profile.take()).filter(|name| !name.is_empty());
+            config.profile = None;
+            if let Some(name) = profile {
+                match profiles.into_iter().find(|(n, _)| *n == name) {
+                    Some((_, options)) => {
+                        yaml_options.overlay(options);
+                        config.profile = Some(name);
+                    },
+                    None => config.file_problem("profile", format!("No profile named `{}`, using the top level values", name)),
+                }
+            }
             // merge the config file arguments into the config structure. If an arg is given on the command line ignore
             // the config file version, otherwise override the default value
-            for arg in env::args() {
-                match arg.as_str() {
-                    "-b" | "--boards"          => yaml_options.Boards         = None,
-                    "-W" | "--width"           => yaml_options.Width          = None,
-                    "-H" | "--height"          => yaml_options.Height         = None,
-                    "-C" | "--cell_size"       => yaml_options.CellSize       = None,
-                    "-d" | "--delay"           => yaml_options.Delay          = None,
-//                    "-e" | "--extended_chance" => yaml_options.ExtendedChance = None,
-                    "-p" | "--preview"         => yaml_options.Preview        = None,
-                    "-s" | "--style"           => yaml_options.Style          = None,
+            for field in config.command_line.iter() {
+                match field.as_str() {
+                    "boards"                   => yaml_options.Boards         = None,
+                    "width"                    => yaml_options.Width          = None,
+                    "height"                   => yaml_options.Height         = None,
+                    "cell_size"                => yaml_options.CellSize       = None,
+                    "delay"                    => yaml_options.Delay          = None,
+//                    "extended_chance"          => yaml_options.ExtendedChance = None,
+                    "preview"                  => yaml_options.Preview        = None,
+                    "style"                    => yaml_options.Style          = None,
+                    "focus"                    => yaml_options.Focus          = None,
+                    "players"                  => yaml_options.Players        = None,
+                    "teams"                    => yaml_options.Teams          = None,
+                    "linked"                   => yaml_options.Linked         = None,
+                    "key_profile"              => yaml_options.KeyProfile     = None,
+                    "ramp_lines"               => yaml_options.RampLines      = None,
+                    "ramp_minutes"             => yaml_options.RampMinutes    = None,
+                    "das"                      => yaml_options.Das            = None,
+                    "arr"                      => yaml_options.Arr            = None,
+                    "soft_drop"                => yaml_options.SoftDrop       = None,
+                    "auto_pause"               => yaml_options.AutoPause      = None,
+                    "gamepads"                 => yaml_options.Gamepads       = None,
+                    "pad_events"               => yaml_options.PadEvents      = None,
+                    "replay_dir"               => yaml_options.ReplayDir      = None,
+                    "save_file"                => yaml_options.SaveFile       = None,
                     _                          => (),
                 };
             }
-            if yaml_options.Boards.is_some()         { config.boards          = yaml_options.Boards.unwrap(); }
-            if yaml_options.Width.is_some()          { config.width           = yaml_options.Width.unwrap(); }
-            if yaml_options.Height.is_some()         { config.height          = yaml_options.Height.unwrap(); }
-            if yaml_options.CellSize.is_some()       { config.cell_size       = yaml_options.CellSize.unwrap(); }
-            if yaml_options.Delay.is_some()          { config.delay           = yaml_options.Delay.unwrap(); }
-//            if yaml_options.ExtendedChance.is_some() { config.extended_chance = yaml_options.ExtendedChance.unwrap(); }
-            if yaml_options.Preview.is_some()        { config.preview         = yaml_options.Preview.unwrap(); }
-//            if yaml_options.InitialPiece.is_some()   { config.initial_piece   = yaml_options.InitialPiece.unwrap(); }
-            if yaml_options.Style.is_some()          { config.style           = expand_filename(&yaml_options.Style.unwrap()); }
+            config.from_file = yaml_options.fields_set();
+            if let Some(boards) = yaml_options.Boards { config.boards          = boards; }
+            if let Some(width) = yaml_options.Width  { config.width           = width; }
+            if let Some(height) = yaml_options.Height { config.height         = height; }
+            if let Some(size) = yaml_options.CellSize { config.cell_size      = size; }
+            if let Some(delay) = yaml_options.Delay  { config.delay           = delay; }
+//            if let Some(chance) = yaml_options.ExtendedChance { config.extended_chance = chance; }
+            if let Some(preview) = yaml_options.Preview { config.preview      = preview; }
+//            if let Some(piece) = yaml_options.InitialPiece { config.initial_piece = piece; }
+            if let Some(style) = yaml_options.Style  { config.style           = expand_filename(&style); }
+            if let Some(focus) = yaml_options.Focus  { config.focus           = focus; }
+            if let Some(players) = yaml_options.Players { config.players      = players; }
+            if let Some(linked) = yaml_options.Linked { config.linked        = Some(linked); }
+            if let Some(lines) = yaml_options.RampLines { config.ramp_lines   = lines; }
+            if let Some(minutes) = yaml_options.RampMinutes { config.ramp_minutes = minutes; }
+            if let Some(das) = yaml_options.Das      { config.das             = das; }
+            if let Some(arr) = yaml_options.Arr      { config.arr             = arr; }
+            if let Some(rate) = yaml_options.SoftDrop { config.soft_drop      = rate; }
+            if let Some(pause) = yaml_options.AutoPause { config.auto_pause   = pause; }
+            if let Some(pads) = yaml_options.Gamepads { config.gamepads       = pads; }
+            if let Some(files) = yaml_options.PadEvents { config.pad_events   = files.iter().map(|f| expand_filename(f)).collect(); }
+            if let Some(dir) = yaml_options.ReplayDir { config.replay_dir     = dir; }
+            if let Some(file) = yaml_options.SaveFile { config.save_file      = file; }
+            let default_keys = KeySections { KeysReplace: yaml_options.KeysReplace, Keys: yaml_options.Keys, PlayerKeys: yaml_options.PlayerKeys,
+                                             Macros: yaml_options.Macros, };
+            for (name, sections) in std::iter::once((DEFAULT_PROFILE.to_string(), default_keys)).chain(yaml_options.KeyProfiles.unwrap_or_default()) {
+                let (keymap, problems) = Keymap::from_sections(&sections, &name);
+                problems.into_iter().for_each(|p| config.file_problem("keys", p));
+                config.keymaps.push((name, keymap));
+            }
+            if let Some(profile) = yaml_options.KeyProfile { config.key_profile = profile; }
+            if let Some(teams) = yaml_options.Teams  {
+                for desc in teams {
+                    match desc.parse::<Team>() {
+                        Ok(team) => config.teams.push(team),
+                        Err(err) => config.file_problem("teams", err + ", ignoring the team"),
+                    }
+                }
+            }
         }
     }
+
+    // the Config fields this sets, which are the yaml names in snake case
+    fn fields_set(&self) -> Vec<String> {
+        let Ok(serde_yaml::Value::Mapping(entries)) = serde_yaml::to_value(self) else { return Vec::new(); };
+        entries.iter().filter(|(_, value)| !value.is_null()).filter_map(|(key, _)| key.as_str()).map(|key| {
+            let mut field = String::new();
+            for (i, c) in key.chars().enumerate() {
+                if c.is_uppercase() && i > 0 { field.push('_'); }
+                field.push(c.to_ascii_lowercase());
+            }
+            field
+        }).collect()
+    }
 }
 
 
+// Writes one keymap profile into the config file, leaving the rest of the file as it is. The default profile is the
+// top level Keys sections, the others go under KeyProfiles.
+pub fn save_key_profile(filename: &str, name: &str, keymap: &Keymap) -> Result<String, String> {
+    let expanded_name = expand_filename(filename);
+    let sections = match serde_yaml::to_value(keymap.to_sections()) {
+        Ok(serde_yaml::Value::Mapping(sections)) => sections,
+        _ => { return Err("Could not convert keymap ".to_string() + name); },
+    };
+    let entries = if name == DEFAULT_PROFILE { sections } else {
+        // the other profiles are kept, so KeyProfiles is read first
+        let yaml = read_mapping(&expanded_name)?;
+        let profiles_key = serde_yaml::Value::from("KeyProfiles");
+        let mut profiles = match yaml.get(&profiles_key) {
+            Some(serde_yaml::Value::Mapping(profiles)) => profiles.clone(),
+            _ => serde_yaml::Mapping::new(),
+        };
+        profiles.insert(serde_yaml::Value::from(name), serde_yaml::Value::Mapping(sections));
+        let mut entries = serde_yaml::Mapping::new();
+        entries.insert(profiles_key, serde_yaml::Value::Mapping(profiles));
+        entries
+    };
+    write_entries(&expanded_name, &entries)?;
+    Ok("Saved keys `".to_string() + name + "` to " + &expanded_name)
+}
+
+// the top level of the config file, or nothing if it is missing or empty
+fn read_mapping(filename: &str) -> Result<serde_yaml::Mapping, String> {
+    let text = std::fs::read_to_string(filename).unwrap_or_default();
+    if text.trim().is_empty() { return Ok(serde_yaml::Mapping::new()); }
+    serde_yaml::from_str(&text).map_err(|err| "Error parsing config file ".to_string() + filename + ": " + &err.to_string())
+}
+
+// Sets the top level ENTRIES in the yaml file FILENAME, which is made if need be. Only the lines of the entries being
+// replaced change, so other keys, comments and blank lines stay as they were. Comments inside a replaced entry can't
+// be matched up with the new values, so they go above it. Entries the file doesn't have yet go on the end.
+//
+// An entry is its key at the start of a line and the lines after it which are indented or start a list item, blank
+// lines and comments included if more of the entry follows them.
+fn write_entries(filename: &str, entries: &serde_yaml::Mapping) -> Result<(), String> {
+    let text = std::fs::read_to_string(filename).unwrap_or_default();
+    let out = update_entries(filename, &text, entries)?;
+    std::fs::write(filename, out).map_err(|err| "Error writing config file ".to_string() + filename + ": " + &err.to_string())
+}
+
+// The text of write_entries(), kept apart from the file so it can be tested. FILENAME is only for messages.
+fn update_entries(filename: &str, text: &str, entries: &serde_yaml::Mapping) -> Result<String, String> {
+    if !text.trim().is_empty() {
+        if let Err(err) = serde_yaml::from_str::<serde_yaml::Mapping>(text) {
+            return Err("Error parsing config file ".to_string() + filename + ", not changing it: " + &err.to_string());
+        }
+    }
+    let mut lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    let continues = |line: &str| line.starts_with([' ', '\t', '-']) && !line.starts_with("---");
+    // blank lines and comments only belong to the entry if more of it follows them
+    let between = |line: &str| line.trim().is_empty() || line.starts_with('#');
+    for (key, value) in entries.iter() {
+        let Some(name) = key.as_str() else { continue; };
+        let mut entry = serde_yaml::Mapping::new();
+        entry.insert(key.clone(), value.clone());
+        let new_lines: Vec<String> = match serde_yaml::to_string(&entry) {
+            Ok(out) => out.lines().map(|l| l.to_string()).collect(),
+            Err(err) => { return Err("Error writing config file ".to_string() + filename + ": " + &err.to_string()); },
+        };
+        let prefix = format!("{}:", name);
+        match lines.iter().position(|l| l.starts_with(&prefix)) {
+            Some(start) => {
+                let mut end = start + 1;
+                let mut i = start + 1;
+                while i < lines.len() && (continues(&lines[i]) || between(&lines[i])) {
+                    if continues(&lines[i]) { end = i + 1; }
+                    i += 1;
+                }
+                let comments: Vec<String> = lines[start + 1..end].iter()
+                    .filter(|l| l.trim_start().starts_with('#')).map(|l| l.trim_start().to_string()).collect();
+                lines.splice(start..end, comments.into_iter().chain(new_lines));
+            },
+            None => lines.extend(new_lines),
+        }
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    if let Err(err) = serde_yaml::from_str::<serde_yaml::Mapping>(&out) {
+        return Err("Could not update config file ".to_string() + filename + ", not changing it: " + &err.to_string());
+    }
+    Ok(out)
+}
+
 // for now just move ~/xxx to ${HOME}/xxx
 fn expand_filename(name: &str) -> String {
-    // allow ~/ for home directory
-    if name[0..2].eq("~/") {
-        let home = dirs::home_dir();
-        let mut home_str = home.unwrap().to_str().unwrap().to_string();
-        home_str.push_str(&name[1..]);
-        home_str
-    } else {
-        name.to_string()
+    // allow ~/ for home directory. Without one the name is left alone, and opening it will report the problem.
+    match (name.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).to_string_lossy().to_string(),
+        _ => name.to_string(),
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::update_entries;
+
+    fn entries(yaml: &str) -> serde_yaml::Mapping { serde_yaml::from_str(yaml).unwrap() }
+
+    #[test]
+    fn replaces_an_entry_and_keeps_the_rest() {
+        let text = "# my settings\nBoards: 2\n\nWidth: 10  # narrow\nUnknown: yes\n";
+        let out = update_entries("test", text, &entries("Boards: 3")).unwrap();
+        assert_eq!(out, "# my settings\nBoards: 3\n\nWidth: 10  # narrow\nUnknown: yes\n");
+    }
+
+    #[test]
+    fn appends_a_missing_entry() {
+        let out = update_entries("test", "Boards: 2\n", &entries("Teams:\n- A=1")).unwrap();
+        assert_eq!(out, "Boards: 2\nTeams:\n- A=1\n");
+        assert_eq!(update_entries("test", "", &entries("Boards: 2")).unwrap(), "Boards: 2\n");
+    }
+
+    #[test]
+    fn comment_inside_an_entry_is_kept_above_it() {
+        let text = "Teams:\n# note\n- A=1\n  # indented\n- C=3\n# about width\nWidth: 10\n";
+        let out = update_entries("test", text, &entries("Teams:\n- B=2")).unwrap();
+        assert_eq!(out, "# note\n# indented\nTeams:\n- B=2\n# about width\nWidth: 10\n");
+        // saving again changes nothing
+        assert_eq!(update_entries("test", &out, &entries("Teams:\n- B=2")).unwrap(), out);
+    }
+
+    #[test]
+    fn key_that_is_a_prefix_of_another() {
+        let text = "KeysReplace: true\nKeys:\n  Left: [a]\n";
+        let out = update_entries("test", text, &entries("Keys:\n  Left: [b]")).unwrap();
+        assert_eq!(out, "KeysReplace: true\nKeys:\n  Left:\n  - b\n");
+        let out = update_entries("test", text, &entries("KeysReplace: false")).unwrap();
+        assert_eq!(out, "KeysReplace: false\nKeys:\n  Left: [a]\n");
+    }
+
+    #[test]
+    fn refuses_a_file_that_does_not_parse() {
+        assert!(update_entries("test", "Boards: [2\n", &entries("Boards: 3")).is_err());
+    }
+}
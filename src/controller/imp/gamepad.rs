@@ -0,0 +1,384 @@
+// Gamepads and joysticks, read straight from the Linux event devices /dev/input/event*
+//
+// Each pad gets a number from 1 in the order it is found, and its buttons and stick directions become key strings like
+// "Pad1:South" or "Pad2:DpadLeft" which go through the keymap the same as keys do. A binding without the number,
+// "Pad:South", is used for any pad that has nothing more specific.
+//
+// A scanner thread looks for new devices every couple of seconds so pads can be plugged in while the game is running,
+// and each pad has a thread of its own that blocks reading it. The threads pass what they find to the GTK thread over
+// a glib channel.
+//
+// Nothing here needs real hardware to try out. A virtual pad made with uinput shows up in /dev/input like any other,
+// and a recorded stream (`cat /dev/input/eventN > file` captures one) can be played back with --pad-events, which
+// reads the raw kernel input_event records and replays them with their original timing.
+//
+// The user running the game needs read access to the devices, usually by being in the input group. Devices that
+// can't be opened are skipped without complaint.
+
+use super::Command;
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use evdev::{Device, EventType, Key};
+use gtk::glib;
+
+// pads beyond this are ignored
+const MAX_PADS: usize = 8;
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+// names for the buttons, after the positions in the kernel's gamepad layout
+const BUTTONS: [(u16, &str); 19] =
+    [(0x130, "South"),          // BTN_SOUTH, A on most pads
+     (0x131, "East"),
+     (0x132, "C"),
+     (0x133, "North"),
+     (0x134, "West"),
+     (0x135, "Z"),
+     (0x136, "TL"),             // shoulder buttons
+     (0x137, "TR"),
+     (0x138, "TL2"),
+     (0x139, "TR2"),
+     (0x13a, "Select"),
+     (0x13b, "Start"),
+     (0x13c, "Mode"),
+     (0x13d, "ThumbL"),         // pressing the sticks
+     (0x13e, "ThumbR"),
+     (0x220, "DpadUp"),
+     (0x221, "DpadDown"),
+     (0x222, "DpadLeft"),
+     (0x223, "DpadRight"),
+];
+
+// axes are turned into a pair of buttons, one for each direction
+const AXES: [(u16, &str, &str); 6] =
+    [(0x00, "StickLeft", "StickRight"),            // ABS_X
+     (0x01, "StickUp", "StickDown"),               // ABS_Y
+     (0x03, "RightStickLeft", "RightStickRight"),  // ABS_RX
+     (0x04, "RightStickUp", "RightStickDown"),     // ABS_RY
+     (0x10, "DpadLeft", "DpadRight"),              // ABS_HAT0X, many pads report the dpad this way
+     (0x11, "DpadUp", "DpadDown"),                 // ABS_HAT0Y
+];
+
+// joysticks number their buttons from BTN_JOYSTICK rather than naming them
+const JOYSTICK_BUTTONS: std::ops::Range<u16> = 0x120..0x130;
+
+// Something that happened on a pad. NAME is the key string, which includes the pad number.
+#[derive(Debug, Clone)]
+pub enum PadEvent {
+    Button { name: String, pressed: bool },
+    Connected { pad: usize, device: String },
+    Disconnected { pad: usize },
+}
+
+// The part of a button name after the pad number, None for buttons that aren't gamepad buttons
+fn button_name(code: u16) -> Option<String> {
+    if let Some((_, name)) = BUTTONS.iter().find(|(c, _)| *c == code) { return Some(name.to_string()); }
+    if JOYSTICK_BUTTONS.contains(&code) { return Some(format!("Button{}", code - JOYSTICK_BUTTONS.start)); }
+    None
+}
+
+// Checks the part of a key string after "PadN:"
+pub fn is_pad_button(name: &str) -> bool {
+    BUTTONS.iter().any(|(_, n)| *n == name) ||
+        AXES.iter().any(|(_, low, high)| *low == name || *high == name) ||
+        name.strip_prefix("Button").and_then(|n| n.parse::<u16>().ok()).is_some_and(|n| n < JOYSTICK_BUTTONS.len() as u16)
+}
+
+// Checks a whole pad key string, "Pad1:South" or "Pad:South"
+pub fn parse_pad_key(desc: &str) -> Result<String, String> {
+    let (pad, button) = desc.split_once(':').ok_or(format!("`{}`: pad buttons are written PadN:BUTTON", desc))?;
+    match pad.strip_prefix("Pad").map(|n| if n.is_empty() { Ok(1) } else { n.parse::<usize>() }) {
+        Some(Ok(n)) if (1..=MAX_PADS).contains(&n) => (),
+        _ => return Err(format!("`{}`: pads are numbered from 1 to {}", desc, MAX_PADS)),
+    }
+    if !is_pad_button(button) { return Err(format!("`{}`: unknown pad button `{}`", desc, button)); }
+    Ok(desc.to_string())
+}
+
+pub fn pad_key(pad: usize, button: &str) -> String { format!("Pad{}:{}", pad, button) }
+
+// the same button on any pad: "Pad3:South" becomes "Pad:South"
+pub fn any_pad_key(key: &str) -> Option<String> {
+    let (_, button) = key.split_once(':')?;
+    Some(format!("Pad:{}", button))
+}
+
+// The translation from raw events to pad buttons, kept apart from the device reading so it works the same on a live
+// pad and a recorded stream
+#[derive(Debug)]
+struct PadState {
+    pad: usize,
+    ranges: HashMap<u16, (i32, i32)>,    // axis code -> (minimum, maximum)
+    pressed: HashSet<String>,
+}
+
+impl PadState {
+    fn new(pad: usize) -> PadState { PadState { pad, ranges: HashMap::new(), pressed: HashSet::new(), } }
+
+    // hats go from -1 to 1, and recordings have no device to ask so the sticks get the common full 16 bit range
+    fn range(&self, code: u16) -> (i32, i32) {
+        match self.ranges.get(&code) {
+            Some(range) => *range,
+            None if code >= 0x10 => (-1, 1),
+            None => (i16::MIN as i32, i16::MAX as i32),
+        }
+    }
+
+    // returns the buttons which changed, with whether they are now down
+    fn translate(&mut self, kind: EventType, code: u16, value: i32) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        if kind == EventType::KEY {
+            // 2 is the kernel's own key repeat, the controller does its own
+            if let (Some(name), 0 | 1) = (button_name(code), value) { self.set(&name, value == 1, &mut changes); }
+        } else if kind == EventType::ABSOLUTE {
+            if let Some((_, low, high)) = AXES.iter().find(|(c, _, _)| *c == code) {
+                // pressed once the axis is more than halfway from the center to either end
+                let (min, max) = self.range(code);
+                let (center, reach) = ((min + max) / 2, (max - min) / 4);
+                self.set(low, value < center - reach.max(0), &mut changes);
+                self.set(high, value > center + reach.max(0), &mut changes);
+            }
+        }
+        changes
+    }
+
+    fn set(&mut self, button: &str, down: bool, changes: &mut Vec<(String, bool)>) {
+        let name = pad_key(self.pad, button);
+        if down == self.pressed.contains(&name) { return; }
+        if down { self.pressed.insert(name.clone()); }
+        else { self.pressed.remove(&name); }
+        changes.push((name, down));
+    }
+
+    // everything that is down gets released when the pad goes away, otherwise a held direction would repeat forever
+    fn release_all(&mut self) -> Vec<(String, bool)> {
+        self.pressed.drain().map(|name| (name, false)).collect()
+    }
+}
+
+// A device is taken as a pad if it has gamepad or joystick buttons. Keyboards and mice never do.
+fn is_pad(device: &Device) -> bool {
+    device.supported_keys().is_some_and(|keys| keys.contains(Key::BTN_SOUTH) || keys.contains(Key::BTN_TRIGGER))
+}
+
+// Pad numbers in use, by slot. A number is given back when its pad is unplugged.
+type Slots = Arc<Mutex<Vec<Option<PathBuf>>>>;
+
+// Starts watching for pads and plays back RECORDINGS. EVENTS gets everything that happens, on the GTK thread.
+pub fn start<F: FnMut(PadEvent) + 'static>(scan: bool, recordings: &[String], mut events: F) {
+    let (sender, receiver) = glib::MainContext::channel::<PadEvent>(glib::PRIORITY_DEFAULT);
+    receiver.attach(None, move |event| { events(event); glib::Continue(true) });
+    let slots: Slots = Arc::new(Mutex::new(vec![None; MAX_PADS]));
+    // recordings take the first numbers so that replays always come in as the same pads
+    for file in recordings {
+        let Some(pad) = claim_slot(&slots, PathBuf::from(file)) else { break; };
+        let (file, sender) = (file.clone(), sender.clone());
+        std::thread::spawn(move || play_recording(&file, pad, &sender));
+    }
+    if scan {
+        std::thread::spawn(move || loop {
+            scan_devices(&slots, &sender);
+            std::thread::sleep(SCAN_INTERVAL);
+        });
+    }
+}
+
+fn claim_slot(slots: &Slots, path: PathBuf) -> Option<usize> {
+    let mut slots = slots.lock().unwrap();
+    let free = slots.iter().position(|s| s.is_none())?;
+    slots[free] = Some(path);
+    Some(free + 1)
+}
+
+fn release_slot(slots: &Slots, pad: usize) { slots.lock().unwrap()[pad - 1] = None; }
+
+fn scan_devices(slots: &Slots, sender: &glib::Sender<PadEvent>) {
+    for (path, device) in evdev::enumerate() {
+        if !is_pad(&device) || slots.lock().unwrap().contains(&Some(path.clone())) { continue; }
+        let Some(pad) = claim_slot(slots, path) else { return; };
+        let (slots, sender) = (Arc::clone(slots), sender.clone());
+        std::thread::spawn(move || read_device(device, pad, &slots, &sender));
+    }
+}
+
+fn read_device(mut device: Device, pad: usize, slots: &Slots, sender: &glib::Sender<PadEvent>) {
+    let mut state = PadState::new(pad);
+    if let Ok(axes) = device.get_abs_state() {
+        for (code, _, _) in AXES.iter() {
+            let info = axes[*code as usize];
+            if info.maximum > info.minimum { state.ranges.insert(*code, (info.minimum, info.maximum)); }
+        }
+    }
+    let name = device.name().unwrap_or("gamepad").to_string();
+    if sender.send(PadEvent::Connected { pad, device: name }).is_err() { return; }
+    // an error here almost always means the pad was unplugged
+    while let Ok(events) = device.fetch_events() {
+        for event in events {
+            for (name, pressed) in state.translate(event.event_type(), event.code(), event.value()) {
+                if sender.send(PadEvent::Button { name, pressed }).is_err() { return; }
+            }
+        }
+    }
+    for (name, pressed) in state.release_all() { let _ = sender.send(PadEvent::Button { name, pressed }); }
+    let _ = sender.send(PadEvent::Disconnected { pad });
+    release_slot(slots, pad);
+}
+
+// A recording is a sequence of the kernel's struct input_event: a 16 byte timeval, then type, code and value
+const RECORD_SIZE: usize = 24;
+
+fn play_recording(file: &str, pad: usize, sender: &glib::Sender<PadEvent>) {
+    let mut input = match std::fs::File::open(file) {
+        Ok(f) => f,
+        Err(err) => { eprintln!("Could not open pad recording `{}`: {}", file, err); return; },
+    };
+    let mut state = PadState::new(pad);
+    if sender.send(PadEvent::Connected { pad, device: file.to_string() }).is_err() { return; }
+    let mut record = [0u8; RECORD_SIZE];
+    let mut last_time: Option<f64> = None;
+    while input.read_exact(&mut record).is_ok() {
+        let (time, kind, code, value) = decode_record(&record);
+        if let Some(last) = last_time {
+            if time > last { std::thread::sleep(Duration::from_secs_f64((time - last).min(10.0))); }
+        }
+        last_time = Some(time);
+        for (name, pressed) in state.translate(kind, code, value) {
+            if sender.send(PadEvent::Button { name, pressed }).is_err() { return; }
+        }
+    }
+    for (name, pressed) in state.release_all() { let _ = sender.send(PadEvent::Button { name, pressed }); }
+    let _ = sender.send(PadEvent::Disconnected { pad });
+}
+
+// (time in seconds, type, code, value)
+fn decode_record(record: &[u8; RECORD_SIZE]) -> (f64, EventType, u16, i32) {
+    let seconds = i64::from_ne_bytes(record[0..8].try_into().unwrap());
+    let micros = i64::from_ne_bytes(record[8..16].try_into().unwrap());
+    let kind = u16::from_ne_bytes(record[16..18].try_into().unwrap());
+    let code = u16::from_ne_bytes(record[18..20].try_into().unwrap());
+    let value = i32::from_ne_bytes(record[20..24].try_into().unwrap());
+    (seconds as f64 + micros as f64 / 1_000_000.0, EventType(kind), code, value)
+}
+
+// The built in pad bindings, the same for every pad. Pad N plays for player N in split games.
+pub const PAD_COMMANDS: [(&str, Command); 14] =
+    [("DpadLeft",   Command::Left),
+     ("DpadRight",  Command::Right),
+     ("DpadDown",   Command::Down),
+     ("DpadUp",     Command::Drop),
+     ("StickLeft",  Command::Left),
+     ("StickRight", Command::Right),
+     ("StickDown",  Command::Down),
+     ("South",      Command::Clockwise),
+     ("East",       Command::CounterClockwise),
+     ("West",       Command::CounterClockwise),
+     ("North",      Command::Drop),
+     ("TR",         Command::NextBoard),
+     ("TL",         Command::PreviousBoard),
+     ("Start",      Command::TogglePause),
+];
+
+// for one numbered pad, or any pad if PAD is None
+pub fn default_bindings(pad: Option<usize>) -> impl Iterator<Item = (String, Command)> {
+    PAD_COMMANDS.iter().map(move |(button, command)| (match pad { Some(n) => pad_key(n, button), None => format!("Pad:{}", button), }, *command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN: u16 = 0x00;
+    const KEY: u16 = 0x01;
+    const ABS: u16 = 0x03;
+
+    // a recording the way `cat /dev/input/eventN` writes it, one event every 10ms
+    fn recording(events: &[(u16, u16, i32)]) -> Vec<u8> {
+        events.iter().enumerate().flat_map(|(i, (kind, code, value))| {
+            let mut record = Vec::with_capacity(RECORD_SIZE);
+            record.extend(1_700_000_000i64.to_ne_bytes());
+            record.extend((i as i64 * 10_000).to_ne_bytes());
+            record.extend(kind.to_ne_bytes());
+            record.extend(code.to_ne_bytes());
+            record.extend(value.to_ne_bytes());
+            record
+        }).collect()
+    }
+
+    // plays DATA through pad 1 and looks the buttons up in the built in bindings
+    fn play(data: &[u8]) -> Vec<(Command, bool)> {
+        let bindings: HashMap<String, Command> = default_bindings(Some(1)).collect();
+        let mut state = PadState::new(1);
+        let mut commands = Vec::new();
+        for record in data.chunks_exact(RECORD_SIZE) {
+            let (_time, kind, code, value) = decode_record(record.try_into().unwrap());
+            for (name, pressed) in state.translate(kind, code, value) {
+                commands.push((*bindings.get(&name).unwrap_or(&Command::Nop), pressed));
+            }
+        }
+        commands.extend(state.release_all().into_iter().map(|(name, pressed)| (bindings[&name], pressed)));
+        commands
+    }
+
+    #[test]
+    fn records_decode_with_their_time() {
+        let data = recording(&[(SYN, 0, 0), (KEY, 0x130, 1)]);
+        let (time, kind, code, value) = decode_record(data[RECORD_SIZE..].try_into().unwrap());
+        assert_eq!((kind, code, value), (EventType::KEY, 0x130, 1));
+        assert!((time - 1_700_000_000.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn button_press_and_release() {
+        let data = recording(&[(KEY, 0x130, 1), (SYN, 0, 0), (KEY, 0x130, 0), (SYN, 0, 0), (KEY, 0x13b, 1), (KEY, 0x13b, 0)]);
+        assert_eq!(play(&data), [(Command::Clockwise, true), (Command::Clockwise, false),
+                                 (Command::TogglePause, true), (Command::TogglePause, false)]);
+    }
+
+    #[test]
+    fn kernel_repeat_is_ignored() {
+        let data = recording(&[(KEY, 0x133, 1), (KEY, 0x133, 2), (KEY, 0x133, 2), (KEY, 0x133, 0)]);
+        assert_eq!(play(&data), [(Command::Drop, true), (Command::Drop, false)]);
+    }
+
+    #[test]
+    fn hat_axis_to_direction() {
+        // left, straight across to right, back to the middle, then down
+        let data = recording(&[(ABS, 0x10, -1), (ABS, 0x10, 1), (ABS, 0x10, 0), (ABS, 0x11, 1), (ABS, 0x11, 1)]);
+        assert_eq!(play(&data), [(Command::Left, true), (Command::Left, false), (Command::Right, true), (Command::Right, false),
+                                 (Command::Down, true), (Command::Down, false)]);
+    }
+
+    #[test]
+    fn stick_needs_to_go_halfway() {
+        let data = recording(&[(ABS, 0x00, -10000), (ABS, 0x00, -20000), (ABS, 0x00, -30000), (ABS, 0x00, 0)]);
+        assert_eq!(play(&data), [(Command::Left, true), (Command::Left, false)]);
+        // a device that reports its own range
+        let mut state = PadState::new(2);
+        state.ranges.insert(0x00, (0, 255));
+        assert_eq!(state.translate(EventType::ABSOLUTE, 0x00, 200), [("Pad2:StickRight".to_string(), true)]);
+        assert!(state.translate(EventType::ABSOLUTE, 0x00, 128).contains(&("Pad2:StickRight".to_string(), false)));
+    }
+
+    #[test]
+    fn unknown_codes_do_nothing() {
+        let data = recording(&[(KEY, 0x10, 1), (ABS, 0x20, 5), (0x02, 0x00, 3)]);
+        assert!(play(&data).is_empty());
+    }
+
+    #[test]
+    fn pad_keys() {
+        assert_eq!(parse_pad_key("Pad1:South"), Ok("Pad1:South".to_string()));
+        assert!(parse_pad_key("Pad:DpadLeft").is_ok());
+        assert!(parse_pad_key("Pad2:Button15").is_ok());
+        assert!(parse_pad_key("Pad2:Button16").is_err());
+        assert!(parse_pad_key("Pad0:South").is_err());
+        assert!(parse_pad_key("Pad9:South").is_err());
+        assert!(parse_pad_key("Pad1:Nothing").is_err());
+        assert!(parse_pad_key("South").is_err());
+        assert_eq!(any_pad_key("Pad3:South"), Some("Pad:South".to_string()));
+    }
+}
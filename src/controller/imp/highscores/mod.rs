@@ -0,0 +1,241 @@
+// High scores, kept in the user's data directory (~/.local/share/tetrii/highscores.yaml on Linux)
+//
+// Scores are only compared with games set up the same way, so each setup has its own table, named by game_key(). A
+// finished game that makes it into the table asks for a name.
+
+use crate::controller::imp::replay::ReplaySettings;
+use crate::board::imp::LineRule;
+use crate::config::DELAY_DEFAULT;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use gtk::{gio, glib};
+use serde::{Deserialize, Serialize};
+
+glib::wrapper! {
+    pub struct HighScores(ObjectSubclass<imp::HighScores>)
+    @extends gtk::Widget, gtk::Window, gtk::ApplicationWindow, @implements gio::ActionMap, gio::ActionGroup;
+}
+
+impl HighScores {
+    pub fn new<P: glib::IsA<gtk::Application>>(app: &P, ) -> Self {
+		glib::Object::builder().property("application", app).build()
+	}
+}
+
+// entries kept for each setup
+pub const MAX_ENTRIES: usize = 10;
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HighScore {
+    pub Name:    String,
+    pub Points:  u32,
+    pub Lines:   u32,
+    pub Seconds: u32,
+    pub Date:    String,
+}
+
+// tables by game_key(), each sorted best first
+pub type ScoreTable = BTreeMap<String, Vec<HighScore>>;
+
+fn scores_file() -> Option<PathBuf> { dirs::data_dir().map(|dir| dir.join("tetrii").join("highscores.yaml")) }
+
+// A missing file is just no scores yet. One that can't be read is reported and treated the same, it will be
+// replaced the next time a score is saved.
+pub fn load_scores() -> ScoreTable {
+    let Some(path) = scores_file() else { return ScoreTable::new(); };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return ScoreTable::new(),
+    };
+    serde_yaml::from_str(&text).unwrap_or_else(|err| {
+        eprintln!("Error parsing high scores {}: {}", path.display(), err);
+        ScoreTable::new()
+    })
+}
+
+pub fn save_scores(table: &ScoreTable) -> Result<String, String> {
+    let path = scores_file().ok_or("No data directory for the high scores".to_string())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("Could not make directory {}: {}", dir.display(), err))?;
+    }
+    let text = serde_yaml::to_string(table).map_err(|err| format!("Error writing high scores {}: {}", path.display(), err))?;
+    std::fs::write(&path, text).map_err(|err| format!("Error writing high scores {}: {}", path.display(), err))?;
+    Ok(format!("Saved high scores to {}", path.display()))
+}
+
+// The name of the table for a setup, which is also what the window shows. Everything that changes how hard the game
+// is goes in it.
+pub fn game_key(settings: &ReplaySettings) -> String {
+    let mut key = format!("{} board{} {}x{}", settings.Boards, if settings.Boards == 1 { "" } else { "s" }, settings.Width, settings.Height);
+    if settings.Players > 1 { key += &format!(", {} players", settings.Players); }
+    match settings.Linked {
+        Some(LineRule::Board) => key += ", linked",
+        Some(LineRule::Field) => key += ", linked field",
+        None => (),
+    }
+    if settings.delay() != DELAY_DEFAULT { key += &format!(", delay {}s", settings.delay()); }
+    if settings.RampLines > 0 || settings.RampMinutes > 0 {
+        key += &format!(", ramp {}/{}", settings.RampLines, settings.RampMinutes);
+    }
+    key
+}
+
+pub fn qualifies(table: &ScoreTable, key: &str, points: u32) -> bool {
+    if points == 0 { return false; }
+    match table.get(key) {
+        Some(entries) => entries.len() < MAX_ENTRIES || entries.last().is_some_and(|e| points > e.Points),
+        None => true,
+    }
+}
+
+// adds ENTRY in its place and returns where that is. Ties go below the scores already there.
+fn insert(table: &mut ScoreTable, key: &str, entry: HighScore) -> usize {
+    let entries = table.entry(key.to_string()).or_default();
+    let rank = entries.iter().position(|e| entry.Points > e.Points).unwrap_or(entries.len());
+    entries.insert(rank, entry);
+    entries.truncate(MAX_ENTRIES);
+    rank
+}
+
+pub mod imp {
+    use std::cell::RefCell;
+
+    use super::{ScoreTable, HighScore, load_scores, save_scores, insert};
+
+    use gtk::{glib, CompositeTemplate};
+    use gtk::glib::clone;
+    use gtk::prelude::*;
+    use gtk::subclass::prelude::*;
+
+    #[derive(Debug, Default)]
+    struct State {
+        table: ScoreTable,
+        keys: Vec<String>,                      // the tables in the dropdown, in its order
+        pending: Option<(String, HighScore)>,   // a new score waiting for its name
+        latest: Option<(String, usize)>,        // the score just added, shown highlighted
+    }
+
+    #[derive(Debug, CompositeTemplate, Default)]
+    #[template(file = "highscores.ui")]
+    pub struct HighScores {
+        state: RefCell<State>,
+
+        #[template_child]
+        game_widget: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        name_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        name_entry: TemplateChild<gtk::Entry>,
+        #[template_child]
+        name_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        scores_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        close_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for HighScores {
+        const NAME: &'static str = "HighScores";
+        type Type = super::HighScores;
+        type ParentType = gtk::ApplicationWindow;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for HighScores {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let scores = self;
+            // closing without a name still keeps the score
+            self.close_button.connect_clicked(clone!(@weak scores => move |_| {
+                scores.name_entered();
+                scores.obj().hide();
+            }));
+            self.name_button.connect_clicked(clone!(@weak scores => move |_| scores.name_entered()));
+            self.name_entry.connect_activate(clone!(@weak scores => move |_| scores.name_entered()));
+            self.game_widget.connect_selected_notify(clone!(@weak scores => move |widget| {
+                let key = scores.state.borrow().keys.get(widget.selected() as usize).cloned();
+                if let Some(key) = key { scores.build_grid(&key); }
+            }));
+        }
+    }
+
+    impl WidgetImpl for HighScores {}
+    impl WindowImpl for HighScores {}
+    impl ApplicationWindowImpl for HighScores {}
+
+    impl HighScores {
+        // shows the table for KEY, which is listed even if it has no scores yet
+        pub fn show_scores(&self, key: &str) {
+            let keys = {
+                let mut state = self.state.borrow_mut();
+                state.table = load_scores();
+                let mut keys: Vec<String> = state.table.keys().cloned().collect();
+                if !keys.iter().any(|k| k == key) { keys.push(key.to_string()); }
+                state.keys = keys.clone();
+                keys
+            };
+            let names: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+            self.game_widget.set_model(Some(&gtk::StringList::new(&names)));
+            self.game_widget.set_selected(keys.iter().position(|k| k == key).unwrap_or(0) as u32);
+            self.build_grid(key);
+            self.obj().present();
+        }
+
+        // ENTRY made it into the table for KEY and is added once it has a name
+        pub fn ask_name(&self, key: &str, entry: HighScore) {
+            self.state.borrow_mut().pending = Some((key.to_string(), entry));
+            self.name_box.set_visible(true);
+            self.show_scores(key);
+            self.name_entry.grab_focus();
+        }
+
+        // the file is read again first in case another game saved a score meanwhile
+        fn name_entered(&self) {
+            let Some((key, mut entry)) = self.state.borrow_mut().pending.take() else { return; };
+            let name = self.name_entry.text().trim().to_string();
+            entry.Name = if name.is_empty() { "Anonymous".to_string() } else { name };
+            self.name_box.set_visible(false);
+            let mut table = load_scores();
+            let rank = insert(&mut table, &key, entry);
+            if let Err(err) = save_scores(&table) { eprintln!("{}", err); }
+            self.state.borrow_mut().latest = Some((key.clone(), rank));
+            self.show_scores(&key);
+        }
+
+        fn build_grid(&self, key: &str) {
+            while let Some(child) = self.scores_grid.first_child() {
+                self.scores_grid.remove(&child);
+            }
+            for (column, title) in ["", "Name", "Points", "Lines", "Time", "Date"].iter().enumerate() {
+                self.scores_grid.attach(&gtk::Label::new(Some(title)), column as i32, 0, 1, 1);
+            }
+            let state = self.state.borrow();
+            let entries = state.table.get(key).cloned().unwrap_or_default();
+            if entries.is_empty() {
+                self.scores_grid.attach(&gtk::Label::new(Some("No scores yet")), 1, 1, 5, 1);
+            }
+            for (i, entry) in entries.iter().enumerate() {
+                let row = i as i32 + 1;
+                let cells = [(i + 1).to_string(), entry.Name.clone(), entry.Points.to_string(), entry.Lines.to_string(),
+                             format!("{:02}:{:02}", entry.Seconds / 60, entry.Seconds % 60), entry.Date.clone()];
+                let latest = state.latest.as_ref().is_some_and(|(k, rank)| k == key && *rank == i);
+                for (column, text) in cells.iter().enumerate() {
+                    let label = gtk::Label::builder().label(text).halign(if column == 1 { gtk::Align::Start } else { gtk::Align::End }).build();
+                    if latest { label.add_css_class("latest"); }
+                    self.scores_grid.attach(&label, column as i32, row, 1, 1);
+                }
+            }
+        }
+    }
+}
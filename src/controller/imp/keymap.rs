@@ -0,0 +1,490 @@
+// Keymaps: which key or mouse button runs which Command
+//
+// The built in bindings are below. The config file can add to them or replace them with a Keys: section for the
+// default keys and a PlayerKeys: list with one section for each player in split keyboard games, e.g.
+//
+//     KeysReplace: false
+//     Keys:
+//       h: Left
+//       Right-Ctrl: Clockwise
+//       1: SetBoard 1
+//     PlayerKeys:
+//       - a: Left
+//         d: Right
+//
+// Macros are named lists of commands which can be bound like any command, with "Macro NAME". A step can repeat with
+// "* N". Macros can't run other macros.
+//
+//     Macros:
+//       spin-left: [Clockwise * 2, Left * 3]
+//     Keys:
+//       z: Macro spin-left
+//
+// Those make up the keymap profile named "default". Other profiles are written the same way under KeyProfiles:,
+// and KeyProfile: (or --keys) picks the one to use.
+//
+//     KeyProfile: left-handed
+//     KeyProfiles:
+//       left-handed:
+//         Keys:
+//           ...
+//
+// Key strings are a key followed by any modifiers, in the same form key_string() builds them. A key is either its
+// GDK name, which is what it types on the current layout without any modifiers ("q", "Left", "KP_4"), or the kernel
+// name for where it is on the keyboard ("KEY_Q" is the key left of W whatever it types). Most of the defaults are
+// positions so they work on any layout. When both kinds are bound the name wins.
+//
+// Mouse buttons are Mouse1 to Mouse3. The wheel is WheelUp and WheelDown, and dragging with a button is Drag1 to Drag3,
+// which only does anything when bound to Follow. Touches on a board are the TOUCH_NAMES below, without modifiers, and
+// a swipe sideways repeats its command once for each column it crosses. Gamepad buttons are PadN:BUTTON, see gamepad.rs for the names.
+
+use super::Command;
+use super::gamepad;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::ser::{Serializer, SerializeMap};
+
+// default commands
+const COMMANDS:[(&str, Command); 59] =
+    [("Right",           Command::Right),
+     ("Left",            Command::Left),
+	 ("Right-Ctrl",      Command::Clockwise),
+     ("Left-Ctrl",       Command::CounterClockwise),
+     ("Down",            Command::Down),
+     ("KEY_Q",           Command::CounterClockwise),
+     ("KEY_Q-Shift",     Command::Left),
+     ("KEY_E",           Command::Clockwise),
+     ("space",           Command::Drop),
+     ("KEY_S",           Command::Resume),
+     ("KEY_T",           Command::TogglePause),
+     ("KEY_P",           Command::Pause),
+     ("Mouse1",          Command::Left),
+     ("Mouse2",          Command::Drop),
+     ("Mouse3",          Command::Right),
+     ("Mouse1-Ctrl",     Command::CounterClockwise),
+     ("Mouse3-Ctrl",     Command::Clockwise),
+     ("WheelUp",         Command::Clockwise),
+     ("WheelDown",       Command::CounterClockwise),
+     ("Drag1",           Command::Follow),
+     ("TouchTap",        Command::Clockwise),
+     ("TouchSwipeLeft",  Command::Left),
+     ("TouchSwipeRight", Command::Right),
+     ("TouchSwipeDown",  Command::Drop),
+     ("TouchSwipeUp",    Command::CounterClockwise),
+     ("TouchTwoFingerTap", Command::TogglePause),
+     ("KEY_1",           Command::SetBoard(0)),
+     ("KEY_2",           Command::SetBoard(1)),
+     ("KEY_3",           Command::SetBoard(2)),
+     ("KEY_4",           Command::SetBoard(3)),
+     ("KEY_5",           Command::SetBoard(4)),
+     ("KEY_RIGHTBRACE",  Command::NextBoard),
+     ("KEY_LEFTBRACE",   Command::PreviousBoard),
+     ("KEY_0-Ctrl",      Command::Cheat(0)),   // force piece
+     ("KEY_1-Ctrl",      Command::Cheat(1)),   // force piece
+     ("KEY_2-Ctrl",      Command::Cheat(2)),
+     ("KEY_3-Ctrl",      Command::Cheat(3)),
+     ("KEY_4-Ctrl",      Command::Cheat(4)),
+     ("KEY_5-Ctrl",      Command::Cheat(5)),
+     ("KEY_6-Ctrl",      Command::Cheat(6)),
+     ("KEY_7-Ctrl",      Command::Cheat(7)),
+     ("KEY_8-Ctrl",      Command::Cheat(8)),
+     ("KEY_9-Ctrl",      Command::Cheat(9)),
+     ("KEY_B-Ctrl",      Command::Cheat(10)),  // use fake bitmap: insert bitmap at BITARRAY and recompile
+     ("KEY_D-Shift",     Command::Cheat(11)),  // dump bitmap binary, easy to see current state
+     ("KEY_D-Ctrl",      Command::Cheat(12)),  // dump bitmap hex, can paste into BITARRAY for debugging
+     ("KEY_P-Ctrl",      Command::Cheat(13)),  
+     ("KEY_S-Ctrl",      Command::Cheat(14)),  // print board substatus
+     ("KEY_R-Ctrl",      Command::Cheat(15)),  // remove second-to-last row
+	 // cheat codes 0-20 are forwarded to the active board, higher codes are handled on the controller in controller_cheat()
+     ("KEY_0-Meta",      Command::Cheat(20)),
+     ("KEY_1-Meta",      Command::Cheat(21)),
+     ("KEY_2-Meta",      Command::Cheat(22)),
+     ("KEY_3-Meta",      Command::Cheat(23)),
+     ("KEY_4-Meta",      Command::Cheat(24)),
+     ("KEY_5-Meta",      Command::Cheat(25)),
+     ("KEY_6-Meta",      Command::Cheat(26)),
+     ("KEY_7-Meta",      Command::Cheat(27)),
+     ("KEY_8-Meta",      Command::Cheat(28)),
+     ("KEY_9-Meta",      Command::Cheat(29)),
+];
+
+// Bindings for split keyboard play, one section per player. There are only enough sensible sections for 4 players,
+// so that is the limit.
+pub const MAX_PLAYERS: u32 = 4;
+const PLAYER_COMMANDS:[&[(&str, Command)]; MAX_PLAYERS as usize] =
+    [&[("KEY_A",       Command::Left),
+       ("KEY_D",       Command::Right),
+       ("KEY_S",       Command::Down),
+       ("KEY_W",       Command::Clockwise),
+       ("KEY_Q",       Command::CounterClockwise),
+       ("KEY_X",       Command::Drop),
+       ("KEY_E",       Command::NextBoard),
+    ],
+     &[("Left",        Command::Left),
+       ("Right",       Command::Right),
+       ("Down",        Command::Down),
+       ("Up",          Command::Clockwise),
+       ("KEY_SLASH",   Command::CounterClockwise),
+       ("Return",      Command::Drop),
+       ("KEY_DOT",     Command::NextBoard),
+     ],
+     &[("KEY_J",       Command::Left),
+       ("KEY_L",       Command::Right),
+       ("KEY_K",       Command::Down),
+       ("KEY_I",       Command::Clockwise),
+       ("KEY_U",       Command::CounterClockwise),
+       ("KEY_M",       Command::Drop),
+       ("KEY_O",       Command::NextBoard),
+     ],
+     &[("KP_4",        Command::Left),
+       ("KP_6",        Command::Right),
+       ("KP_5",        Command::Down),
+       ("KP_8",        Command::Clockwise),
+       ("KP_7",        Command::CounterClockwise),
+       ("KP_0",        Command::Drop),
+       ("KP_9",        Command::NextBoard),
+     ],
+];
+
+
+const MODIFIERS: [&str; 4] = ["Shift", "Alt", "Ctrl", "Meta"];
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+// the commands the editor always lists, whether or not they are bound
+pub const EDITABLE_COMMANDS: [Command; 17] =
+    [Command::Left, Command::Right, Command::Down, Command::Clockwise, Command::CounterClockwise, Command::Drop, Command::Follow,
+     Command::Pause, Command::Resume, Command::TogglePause, Command::NextBoard, Command::PreviousBoard,
+     Command::SetBoard(0), Command::SetBoard(1), Command::SetBoard(2), Command::SetBoard(3), Command::SetBoard(4), ];
+
+// One keymap as it is written in the config file
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KeySections {
+    pub KeysReplace: Option<bool>,
+    pub Keys:        Option<KeyBindings>,
+    pub PlayerKeys:  Option<Vec<KeyBindings>>,
+    pub Macros:      Option<BTreeMap<String, Vec<String>>>,
+}
+
+// The bindings of one section of the config file, in the order they appear. Reading into a map would quietly drop
+// duplicated keys, this keeps them so they can be reported.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyBindings(pub Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeyBindings, D::Error> {
+        struct BindingsVisitor;
+        impl<'de> Visitor<'de> for BindingsVisitor {
+            type Value = KeyBindings;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("a map of keys to commands") }
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<KeyBindings, A::Error> {
+                let mut bindings = Vec::<(String, String)>::new();
+                while let Some(entry) = map.next_entry::<String, String>()? { bindings.push(entry); }
+                Ok(KeyBindings(bindings))
+            }
+        }
+        deserializer.deserialize_map(BindingsVisitor)
+    }
+}
+
+impl Serialize for KeyBindings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, command) in self.0.iter() { map.serialize_entry(key, command)?; }
+        map.end()
+    }
+}
+
+// The keymap in use: the default section and one section per player. Player sections only apply when there is more
+// than one player, and are checked before the default section.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    keys: HashMap<String, Command>,
+    players: Vec<HashMap<String, Command>>,
+    macros: Vec<(String, Vec<Command>)>,    // Command::Macro(i) runs macros[i]
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        // every pad plays for the only player, in split games pad N goes with player N
+        let mut keys = section_from(&COMMANDS);
+        keys.extend(gamepad::default_bindings(None));
+        let players = PLAYER_COMMANDS.iter().enumerate()
+            .map(|(i, section)| { let mut keys = section_from(section); keys.extend(gamepad::default_bindings(Some(i + 1))); keys })
+            .collect();
+        Keymap { keys, players, macros: Vec::new(), }
+    }
+}
+
+fn section_from(table: &[(&str, Command)]) -> HashMap<String, Command> {
+    table.iter().map(|(key, command)| (key.to_string(), *command)).collect()
+}
+
+impl Keymap {
+    // Applies the config file sections to the built in keymap. Bad entries are skipped and described in the returned
+    // list so the caller can report them.
+    pub fn from_sections(sections: &KeySections, profile: &str) -> (Keymap, Vec<String>) {
+        let mut keymap = Keymap::default();
+        let mut problems = Vec::<String>::new();
+        if sections.KeysReplace.unwrap_or(false) {
+            keymap.keys.clear();
+            keymap.players.iter_mut().for_each(|section| section.clear());
+        }
+        // macros first, the bindings refer to them
+        for (name, steps) in sections.Macros.iter().flatten() {
+            match parse_macro(steps) {
+                Ok(commands) => keymap.macros.push((name.clone(), commands)),
+                Err(err) => problems.push(format!("{} Macros: `{}`: {}", profile, name, err)),
+            }
+        }
+        let macros = keymap.macros.clone();
+        if let Some(keys) = &sections.Keys {
+            apply_section(&mut keymap.keys, keys, &macros, &format!("{} Keys", profile), &mut problems);
+        }
+        for (i, bindings) in sections.PlayerKeys.iter().flatten().enumerate() {
+            match keymap.players.get_mut(i) {
+                Some(section) => apply_section(section, bindings, &macros, &format!("{} PlayerKeys {}", profile, i + 1), &mut problems),
+                None => problems.push(format!("{} PlayerKeys: only {} players are supported, section {} ignored", profile, MAX_PLAYERS, i + 1)),
+            }
+        }
+        (keymap, problems)
+    }
+
+    // the whole keymap is written out, so it replaces the built in one when read back
+    pub fn to_sections(&self) -> KeySections {
+        let (keys, players) = self.bindings();
+        let macros = if self.macros.is_empty() { None } else {
+            Some(self.macros.iter().map(|(name, steps)| (name.clone(), macro_strings(steps))).collect())
+        };
+        KeySections { KeysReplace: Some(true), Keys: Some(keys), PlayerKeys: Some(players), Macros: macros, }
+    }
+
+    // the name the config file uses, which for macros is only known here
+    pub fn command_name(&self, command: Command) -> String {
+        match command {
+            Command::Macro(index) => match self.macros.get(index as usize) {
+                Some((name, _)) => format!("Macro {}", name),
+                None => command.to_string(),
+            },
+            _ => command.to_string(),
+        }
+    }
+
+    pub fn macro_steps(&self, index: u32) -> Vec<Command> {
+        self.macros.get(index as usize).map(|(_, steps)| steps.clone()).unwrap_or_default()
+    }
+
+    // the player the key belongs to as well as the command
+    pub fn get(&self, key: &str, players: usize) -> (usize, Command) {
+        if players > 1 {
+            for (player, section) in self.players.iter().enumerate().take(players) {
+                if let Some(command) = section.get(key) { return (player, *command); }
+            }
+        }
+        (0, *self.keys.get(key).unwrap_or(&Command::Nop))
+    }
+
+    // sorted so saved files come out the same every time
+    pub fn bindings(&self) -> (KeyBindings, Vec<KeyBindings>) {
+        (self.section_bindings(&self.keys), self.players.iter().map(|section| self.section_bindings(section)).collect())
+    }
+
+    //
+    // Editing. Section 0 is the default keys, 1 on are the players.
+    //
+    pub fn section_count(&self) -> usize { self.players.len() + 1 }
+
+    fn section(&self, section: usize) -> Option<&HashMap<String, Command>> {
+        if section == 0 { Some(&self.keys) } else { self.players.get(section - 1) }
+    }
+
+    fn section_mut(&mut self, section: usize) -> Option<&mut HashMap<String, Command>> {
+        if section == 0 { Some(&mut self.keys) } else { self.players.get_mut(section - 1) }
+    }
+
+    pub fn keys_for(&self, section: usize, command: Command) -> Vec<String> {
+        let mut keys: Vec<String> = self.section(section).into_iter().flatten()
+            .filter(|(_key, c)| **c == command).map(|(key, _c)| key.clone()).collect();
+        keys.sort();
+        keys
+    }
+
+    // returns the command the key ran before, if it was bound to something else
+    pub fn bind(&mut self, section: usize, key: &str, command: Command) -> Option<Command> {
+        let previous = self.section_mut(section)?.insert(key.to_string(), command);
+        previous.filter(|p| *p != command)
+    }
+
+    pub fn unbind(&mut self, section: usize, command: Command) {
+        if let Some(keys) = self.section_mut(section) { keys.retain(|_key, c| *c != command); }
+    }
+
+    // Keys of SECTION that clash with another section. With one player only the default keys are used. With more, the
+    // players' sections are checked in order and the default keys only run what none of them bind, so players reusing
+    // default keys is expected. A key in two player sections only ever works for the first of them. A section can't
+    // hold a key twice, binding it again replaces the command.
+    pub fn conflicts(&self, section: usize) -> HashSet<String> {
+        if section == 0 { return HashSet::new(); }
+        let Some(keys) = self.section(section) else { return HashSet::new(); };
+        keys.keys()
+            .filter(|key| self.players.iter().enumerate().any(|(player, other)| player + 1 != section && other.contains_key(*key)))
+            .cloned()
+            .collect()
+    }
+
+    // every command that is bound somewhere, in the order the editor lists them. Macros are listed even when unbound.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = EDITABLE_COMMANDS.to_vec();
+        commands.extend((0..self.macros.len() as u32).map(Command::Macro));
+        let mut others: Vec<Command> = std::iter::once(&self.keys).chain(self.players.iter())
+            .flat_map(|section| section.values().copied())
+            .filter(|c| !commands.contains(c) && *c != Command::Nop)
+            .collect();
+        others.sort_by_key(|c| if let Command::Cheat(code) = c { *code } else { 0 });
+        others.dedup();
+        commands.append(&mut others);
+        commands
+    }
+
+    fn section_bindings(&self, section: &HashMap<String, Command>) -> KeyBindings {
+        let mut bindings: Vec<(String, String)> = section.iter().map(|(key, command)| (key.clone(), self.command_name(*command))).collect();
+        bindings.sort();
+        KeyBindings(bindings)
+    }
+}
+
+// A macro step is a command, optionally followed by "* N" to repeat it
+const MACRO_REPEAT_MAX: usize = 20;
+
+fn parse_macro(steps: &[String]) -> Result<Vec<Command>, String> {
+    let mut commands = Vec::<Command>::new();
+    for step in steps {
+        let (desc, count) = match step.split_once('*') {
+            Some((desc, count)) => match count.trim().parse::<usize>() {
+                Ok(n) if (1..=MACRO_REPEAT_MAX).contains(&n) => (desc.trim(), n),
+                _ => return Err(format!("`{}`: the repeat count must be between 1 and {}", step, MACRO_REPEAT_MAX)),
+            },
+            None => (step.trim(), 1),
+        };
+        if desc.starts_with("Macro") { return Err(format!("`{}`: macros can't run other macros", step)); }
+        let command = desc.parse::<Command>().map_err(|err| format!("`{}`: {}", step, err))?;
+        commands.extend(std::iter::repeat_n(command, count));
+    }
+    Ok(commands)
+}
+
+// the steps written back, with runs of one command put back together
+fn macro_strings(steps: &[Command]) -> Vec<String> {
+    let mut strings = Vec::<String>::new();
+    let mut i = 0;
+    while i < steps.len() {
+        let run = steps[i..].iter().take_while(|c| **c == steps[i]).count().min(MACRO_REPEAT_MAX);
+        strings.push(if run == 1 { steps[i].to_string() } else { format!("{} * {}", steps[i], run) });
+        i += run;
+    }
+    strings
+}
+
+fn apply_section(section: &mut HashMap<String, Command>, bindings: &KeyBindings, macros: &[(String, Vec<Command>)], name: &str,
+                 problems: &mut Vec<String>) {
+    let mut seen = HashMap::<String, String>::new();
+    for (key, command_name) in bindings.0.iter() {
+        let key_string = match parse_key_string(key) {
+            Ok(k) => k,
+            Err(err) => { problems.push(format!("{}: {}", name, err)); continue; },
+        };
+        let parsed = match command_name.strip_prefix("Macro ") {
+            Some(macro_name) => macros.iter().position(|(n, _)| n == macro_name.trim()).map(|i| Command::Macro(i as u32))
+                .ok_or(format!("there is no macro named `{}`", macro_name.trim())),
+            None => command_name.parse::<Command>(),
+        };
+        let command = match parsed {
+            Ok(c) => c,
+            Err(err) => { problems.push(format!("{}: `{}`: {}", name, key, err)); continue; },
+        };
+        if let Some(previous) = seen.insert(key_string.clone(), command_name.clone()) {
+            problems.push(format!("{}: `{}` is bound more than once ({} and {}), using {}", name, key, previous, command_name, command_name));
+        }
+        section.insert(key_string, command);
+    }
+}
+
+// Checks a key string and puts it in the form input is matched in: the lower case GDK key name, then the modifiers in
+// the order Shift, Alt, Ctrl, Meta.
+pub fn parse_key_string(desc: &str) -> Result<String, String> {
+    if desc.starts_with("Pad") && desc.contains(':') { return gamepad::parse_pad_key(desc); }
+    let mut parts = desc.split('-');
+    let name = parts.next().unwrap_or("");
+    let mut key = if is_pointer_name(name) || is_physical_name(name) {
+        name.to_string()
+    } else {
+        match gdk4::Key::from_name(name).and_then(|k| k.to_lower().name()) {
+            Some(n) => n.to_string(),
+            None => return Err(format!("`{}`: unknown key `{}`", desc, name)),
+        }
+    };
+    let mut modifiers = Vec::<&str>::new();
+    for modifier in parts {
+        match MODIFIERS.iter().find(|m| **m == modifier) {
+            Some(m) => if !modifiers.contains(m) { modifiers.push(m); },
+            None => return Err(format!("`{}`: unknown modifier `{}`, use Shift, Alt, Ctrl or Meta", desc, modifier)),
+        }
+    }
+    for modifier in MODIFIERS.iter().filter(|m| modifiers.contains(m)) {
+        key.push('-');
+        key.push_str(modifier);
+    }
+    Ok(key)
+}
+
+// The name a key types on layout GROUP with no modifiers down. The key in an event has already been changed by
+// Shift or Alt, which would make "1-Shift" arrive as "exclam-Shift".
+pub fn base_key_name(key: gdk4::Key, code: u32, group: u32) -> String {
+    use gdk4::prelude::DisplayExtManual;
+    let base = gdk4::Display::default()
+        .and_then(|display| display.translate_key(code, gdk4::ModifierType::empty(), group as i32))
+        .map_or(key, |(base, _, _, _)| base);
+    base.to_lower().name().or_else(|| key.to_lower().name()).map(|n| n.to_string()).unwrap_or_default()
+}
+
+// The kernel name for a key's position. GDK keycodes on Linux are the kernel's plus 8, under X11 and Wayland alike.
+pub fn physical_name(code: u32) -> Option<String> {
+    let name = format!("{:?}", evdev::Key::new(code.checked_sub(8)? as u16));
+    if is_physical_name(&name) { Some(name) } else { None }
+}
+
+fn is_physical_name(name: &str) -> bool {
+    name.starts_with("KEY_") && name.parse::<evdev::Key>().is_ok()
+}
+
+// in the order of the TOUCH_* gesture codes in board/imp.rs
+pub const TOUCH_NAMES: [&str; 6] = ["TouchTap", "TouchSwipeLeft", "TouchSwipeRight", "TouchSwipeDown", "TouchSwipeUp", "TouchTwoFingerTap"];
+
+// the names for mouse buttons, the wheel, drags and touches, which aren't GDK keys
+fn is_pointer_name(name: &str) -> bool {
+    let numbered = |prefix: &str| name.strip_prefix(prefix).is_some_and(|n| !n.is_empty() && n.parse::<u32>().is_ok());
+    numbered("Mouse") || numbered("Drag") || name == "WheelUp" || name == "WheelDown" || TOUCH_NAMES.contains(&name)
+}
+
+// Builds the key string for a key or "MouseN" with the modifiers that are down
+pub fn key_string(name: &str, mods: gdk4::ModifierType) -> String {
+    let mut key = name.to_string();
+    for (mask, modifier) in [gdk4::ModifierType::SHIFT_MASK, gdk4::ModifierType::ALT_MASK,
+                             gdk4::ModifierType::CONTROL_MASK, gdk4::ModifierType::META_MASK].iter().zip(MODIFIERS.iter()) {
+        if mods.contains(*mask) {
+            key.push('-');
+            key.push_str(modifier);
+        }
+    }
+    key
+}
+
+// True for the keys that are only modifiers, they can't be bound by themselves
+pub fn is_modifier_key(name: &str) -> bool {
+    matches!(name, "Shift_L" | "Shift_R" | "Control_L" | "Control_R" | "Alt_L" | "Alt_R" | "Meta_L" | "Meta_R"
+             | "Super_L" | "Super_R" | "ISO_Level3_Shift" | "Caps_Lock")
+}
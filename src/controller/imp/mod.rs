@@ -1,15 +1,29 @@
 // Was src/controller/imp.rs
 
 pub mod summary;
+pub mod keymap;
+pub mod gamepad;
+pub mod replay;
+pub mod savegame;
+pub mod highscores;
+pub mod statistics;
 
 use crate::BOARDS;
 use crate::Board;
 use crate::CONTROLLER;
 use crate::controller_inst;
 use crate::controller::imp::summary::Summary as SummaryWidget;
+use crate::controller::imp::highscores::{HighScores as HighScoresWidget, HighScore};
+use crate::controller::imp::statistics::{Statistics as StatisticsWidget, GameRecord};
+use crate::controller::imp::keymap::{Keymap, MAX_PLAYERS};
+use crate::controller::imp::replay::{Recorder, ReplaySettings, Playback};
+use crate::config::{Team, DAS_DEFAULT, ARR_DEFAULT, SOFT_DROP_DEFAULT};
+use crate::board::imp::LineRule;
 
 use std::rc::Rc;
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 use gtk::{glib, CompositeTemplate};
 use gtk::prelude::*;
@@ -17,6 +31,7 @@ use gtk::subclass::prelude::*;
 use gtk::glib::clone;
 use gtk::glib::subclass::Signal;
 use gdk4::ModifierType;
+use serde::{Deserialize, Serialize};
 // this gives a warning as unused, but removing it breaks the Default for Internal
 use std::cell::{Cell, RefCell};
 use once_cell::sync::Lazy;
@@ -44,48 +59,151 @@ fn boards_add(board: Board) { unsafe { BOARDS.push(board); }}
 // end STATIC MUTS
 //
 
-static COMMANDMAP: Lazy<HashMap<String, Command>> = Lazy::new(|| {
-    let mut hashmap: HashMap<String, Command> = HashMap::new();
-    COMMANDS.iter().for_each(|desc| { hashmap.insert(desc.0.to_string(), desc.1); });
-    hashmap
-});
-fn command_map_get(key: &String) -> Command { *COMMANDMAP.get(key).unwrap_or(&Command::Nop)}
-
 #[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub enum State {#[default] Initial, Paused, Running, Finished, }
 
+// How the active board gets chosen. Number keys and Next/Previous always work, the policy only decides what the
+// mouse and the game itself are allowed to do. The order here is the order of the dropdown in the Options window.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusPolicy {Keys,              // only keyboard commands change the board
+                      #[default] Hover,  // moving the pointer over a board selects it
+                      Click,             // clicking a board selects it, clicks on the active board are commands
+                      Danger,            // follow the board with the highest stack
+}
+
+impl FocusPolicy {
+	pub fn from_index(index: u32) -> FocusPolicy {
+		match index {
+			0 => FocusPolicy::Keys,
+			2 => FocusPolicy::Click,
+			3 => FocusPolicy::Danger,
+			_ => FocusPolicy::Hover,
+		}
+	}
+	pub fn index(&self) -> u32 { *self as u32 }
+}
+
 //#[derive(Debug, Default)]
 #[derive(Debug, Default, CompositeTemplate)]
 #[template(file = "controller.ui")]
 pub struct Controller {
     pub internal: Rc<RefCell<Internal>>,
+    recorder: RefCell<Recorder>,    // kept apart from INTERNAL, the boards report to it while INTERNAL may be borrowed
+    playback: RefCell<Option<Playback>>,    // set when showing a replay instead of playing
+    delay: Cell<f64>,               // the boards' starting gravity in seconds, 0 for their own default. Also outside INTERNAL.
     
     #[template_child]
     pub boards_container: TemplateChild<gtk::Box>,
     #[template_child]
+    pub paused_overlay: TemplateChild<gtk::Label>,
+    #[template_child]
     pub total_points: TemplateChild<gtk::Label>,
     #[template_child]
     pub total_lines: TemplateChild<gtk::Label>,
     #[template_child]
     pub time_disp: TemplateChild<gtk::Label>,
     #[template_child]
+    pub players_box: TemplateChild<gtk::Box>,
+    #[template_child]
+    pub teams_box: TemplateChild<gtk::Box>,
+    #[template_child]
+    pub replay_bar: TemplateChild<gtk::Box>,
+    #[template_child]
+    pub replay_play_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub replay_step_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub replay_speed_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub replay_position: TemplateChild<gtk::Scale>,
+    #[template_child]
     pub start_button: TemplateChild<gtk::Button>,
     #[template_child]
     pub quit_button: TemplateChild<gtk::Button>,
     #[template_child]
     pub options_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub save_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub load_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub scores_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub stats_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
     //    pub grid: gtk::Grid,
 }
 
 #[derive(Debug, Default)]
 pub struct Internal {
-    active: u32,        // the board to direct commands to
+    players: Vec<Player>,
+    player_count: u32,    // requested number of players, limited to the number of boards when they are assigned
+    team_config: Vec<Team>,
+    teams: Vec<TeamScore>,
+    linked: Option<LineRule>,    // when set the boards form one wide field
+    ramp: (u32, u32),            // (lines, minutes) between boards joining a progressive game, 0 to turn off
+    activation: Vec<u32>,        // board ids in the order they join the game
+    active_count: usize,         // how many of ACTIVATION are in play
     score: (u32, u32),    // (points, completed lines)
     state: State,
-	modifier_bits: u32,
+	keymap: Keymap,
+	auto_repeat: AutoRepeat,
+	held: HashMap<String, u32>,   // movement keys being repeated, with the serial number of their repeat timer
+	held_serial: u32,
+	auto_pause: bool,             // pause when the window stops being the active one
+	replay_dir: String,           // where finished games are saved, empty to not save them
+	save_file: String,            // where Save game writes to and Load game reads from
+	summary_out: Option<String>,  // the summary of each finished game is written here
+	focus: FocusPolicy,
 	seconds: u32,
+	pieces: u32,                  // pieces landed on all the boards, replays step by them
 	clock: Clock,
     pub summary: Option<SummaryWidget>,
+    pub highscores: Option<HighScoresWidget>,
+    pub statistics: Option<StatisticsWidget>,
+}
+
+// Timing for repeating held movement keys, in milliseconds. Left and Right wait DELAY before repeating every RATE,
+// Down repeats every SOFT_DROP from the start. Other commands are not repeated by the game.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AutoRepeat {
+    pub delay: u32,
+    pub rate: u32,
+    pub soft_drop: u32,
+}
+
+impl Default for AutoRepeat {
+    fn default() -> AutoRepeat { AutoRepeat { delay: DAS_DEFAULT, rate: ARR_DEFAULT, soft_drop: SOFT_DROP_DEFAULT, } }
+}
+
+impl AutoRepeat {
+	// (first delay, then interval) for commands that repeat
+	fn timing(&self, command: Command) -> Option<(u32, u32)> {
+		match command {
+			Command::Left | Command::Right => Some((self.delay, self.rate)),
+			Command::Down => Some((self.soft_drop, self.soft_drop)),
+			_ => None,
+		}
+	}
+}
+
+// In a single player game there is one Player who owns all the boards
+#[derive(Debug)]
+struct Player {
+    boards: Vec<u32>,     // ids of the boards this player controls, in order
+    active: u32,          // the board to direct this player's commands to
+    score: (u32, u32),    // (points, completed lines)
+    label: gtk::Label,    // score display in the controller window
+}
+
+// A team from the config restricted to the boards actually in play, with its running score
+#[derive(Debug)]
+struct TeamScore {
+    team: Team,
+    score: (u32, u32),    // (points, completed lines)
+    label: gtk::Label,
 }
 
 #[glib::object_subclass]
@@ -111,16 +229,21 @@ impl ObjectImpl for Controller {
         self.quit_button.connect_clicked(clone!(@weak gcontroller => move |_| { gcontroller.exit(); }));
         self.options_button.connect_clicked( |_button| { Controller::options(true); });
         self.start_button.connect_clicked( |_button| { controller_inst().toggle_state(); });
+        self.save_button.connect_clicked( |_button| { controller_inst().save_clicked(); });
+        self.load_button.connect_clicked( |_button| { controller_inst().load_clicked(); });
+        self.scores_button.connect_clicked( |_button| { controller_inst().show_high_scores(); });
+        self.stats_button.connect_clicked( |_button| { controller_inst().show_statistics(); });
         let key_handler = gtk::EventControllerKey::new();
         self.obj().add_controller(&key_handler);
-        key_handler.connect_key_pressed(move |_ctlr, key, _code, _mods| {
-			set_modifier(key, true);
-            controller_inst().do_command(keyboard_input(key));
+        key_handler.connect_key_pressed(move |ctlr, key, code, mods| {
+            controller_inst().key_pressed(key, code, ctlr.group(), mods);
             gtk::Inhibit(true)
         });
-        key_handler.connect_key_released(move |_ctlr, key, _code, _mods| {
-			set_modifier(key, false);
+        key_handler.connect_key_released(move |_ctlr, _key, code, _mods| {
+            controller_inst().key_released(code);
         });
+        // the release is never seen if the window loses focus while a key is down, so stop repeating then
+        self.obj().connect_is_active_notify(|window| { if !window.is_active() { controller_inst().deactivated(); }});
     }
 
     fn signals() -> &'static [Signal] {
@@ -134,13 +257,25 @@ impl ObjectImpl for Controller {
                  .param_types([u32::static_type(), ])
                  .build(),
                  Signal::builder("mouse-click")
-                 // board id, which-mouse
-                 .param_types([u32::static_type(), u32::static_type(), ])
+                 // board id, which-mouse, modifier bits
+                 .param_types([u32::static_type(), u32::static_type(), u32::static_type(), ])
                  .build(),
                  Signal::builder("select")
                  // board id, 
                  .param_types([u32::static_type(), ])
                  .build(),
+                 Signal::builder("mouse-wheel")
+                 // board id, 0 for up and 1 for down, modifier bits
+                 .param_types([u32::static_type(), u32::static_type(), u32::static_type(), ])
+                 .build(),
+                 Signal::builder("touch")
+                 // board id, index in keymap::TOUCH_NAMES, how many times (columns crossed for swipes)
+                 .param_types([u32::static_type(), u32::static_type(), u32::static_type(), ])
+                 .build(),
+                 Signal::builder("mouse-drag")
+                 // board id, which-mouse, column under the pointer counting from the left, modifier bits
+                 .param_types([u32::static_type(), u32::static_type(), u32::static_type(), u32::static_type(), ])
+                 .build(),
             ]
         });
         SIGNALS.as_ref()
@@ -157,7 +292,7 @@ impl ApplicationWindowImpl for Controller {}
 //
 //////////////////////////////////////////////////////////////////
 
-// if I have time and interest the commands will be configurable through the .tetrii file
+// Bindings for these are in keymap.rs and can be changed in the config file
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum Command {Left,               // commands that are sent to the Boards
                   Right,
@@ -169,7 +304,11 @@ pub enum Command {Left,               // commands that are sent to the Boards
                   Resume,
                   TogglePause,
                   SetBoard(u32),
+                  NextBoard,
+                  PreviousBoard,
                   Cheat(u32),
+                  Follow,             // move the piece to the pointer's column while dragging
+                  Macro(u32),         // a list of commands from the keymap, see Keymap::macro_steps()
                   #[default] Nop,
 }
 
@@ -181,6 +320,50 @@ impl Command {
 	}
 }
 
+// These are the names used in the config file. Board numbers count from 1 there, the same as the number keys.
+impl fmt::Display for Command {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Command::SetBoard(id) => write!(f, "SetBoard {}", id + 1),
+			Command::Cheat(code) => write!(f, "Cheat {}", code),
+			// only the keymap knows the name, see Keymap::command_name()
+			Command::Macro(index) => write!(f, "Macro #{}", index + 1),
+			_ => write!(f, "{:?}", self),
+		}
+	}
+}
+
+impl FromStr for Command {
+	type Err = String;
+	fn from_str(desc: &str) -> Result<Command, String> {
+		let mut words = desc.split_whitespace();
+		let name = words.next().unwrap_or("");
+		let arg = words.next().map(|w| w.parse::<u32>());
+		let command = match (name, arg) {
+			("Left", None)             => Command::Left,
+			("Right", None)            => Command::Right,
+			("Down", None)             => Command::Down,
+			("Clockwise", None)        => Command::Clockwise,
+			("CounterClockwise", None) => Command::CounterClockwise,
+			("Drop", None)             => Command::Drop,
+			("Pause", None)            => Command::Pause,
+			("Resume", None)           => Command::Resume,
+			("TogglePause", None)      => Command::TogglePause,
+			("NextBoard", None)        => Command::NextBoard,
+			("PreviousBoard", None)    => Command::PreviousBoard,
+			("Follow", None)           => Command::Follow,
+			("Nop", None)              => Command::Nop,
+			("SetBoard", Some(Ok(n))) if (1..=5).contains(&n) => Command::SetBoard(n - 1),
+			("Cheat", Some(Ok(n)))     => Command::Cheat(n),
+			("SetBoard", _)            => return Err(format!("`{}`: SetBoard needs a board number from 1 to 5", desc)),
+			("Cheat", _)               => return Err(format!("`{}`: Cheat needs a code number", desc)),
+			_                          => return Err(format!("unknown command `{}`", desc)),
+		};
+		if words.next().is_some() { return Err(format!("unknown command `{}`", desc)); }
+		Ok(command)
+	}
+}
+
 // command mask used to send to BOARD. All others are handled locally
 use crate::board::imp::{CMD_LEFT,
 						CMD_RIGHT,
@@ -193,82 +376,180 @@ use crate::board::imp::{CMD_LEFT,
 						CMD_START,
 						CMD_STOP,
 						CMD_DROP,
+						CMD_COLUMN,
 };
 
-// default commands
-const COMMANDS:[(&str, Command); 48] =
-    [("Right",       Command::Right),
-     ("Left",        Command::Left),
-	 ("Right-Ctrl",  Command::Clockwise),
-     ("Left-Ctrl",   Command::CounterClockwise),
-     ("Down",        Command::Down),
-     ("q",           Command::CounterClockwise),
-     ("q-Shift",     Command::Left),
-     ("e",           Command::Clockwise),
-     ("space",       Command::Drop),
-     ("s",           Command::Resume),
-     ("t",           Command::TogglePause),
-     ("p",           Command::Pause),
-     ("Mouse1",      Command::Left),
-     ("Mouse2",      Command::Drop),
-     ("Mouse3",      Command::Right),
-     ("Mouse1-Ctrl", Command::CounterClockwise),
-     ("Mouse3-Ctrl", Command::Clockwise),
-     ("1",           Command::SetBoard(0)),
-     ("2",           Command::SetBoard(1)),
-     ("3",           Command::SetBoard(2)),
-     ("4",           Command::SetBoard(3)),
-     ("5",           Command::SetBoard(4)),
-     ("0-Ctrl",      Command::Cheat(0)),   // force piece
-     ("1-Ctrl",      Command::Cheat(1)),   // force piece
-     ("2-Ctrl",      Command::Cheat(2)),
-     ("3-Ctrl",      Command::Cheat(3)),
-     ("4-Ctrl",      Command::Cheat(4)),
-     ("5-Ctrl",      Command::Cheat(5)),
-     ("6-Ctrl",      Command::Cheat(6)),
-     ("7-Ctrl",      Command::Cheat(7)),
-     ("8-Ctrl",      Command::Cheat(8)),
-     ("9-Ctrl",      Command::Cheat(9)),
-     ("b-Ctrl",      Command::Cheat(10)),  // use fake bitmap: insert bitmap at BITARRAY and recompile
-     ("d-Shift",     Command::Cheat(11)),  // dump bitmap binary, easy to see current state
-     ("d-Ctrl",      Command::Cheat(12)),  // dump bitmap hex, can paste into BITARRAY for debugging
-     ("p-Ctrl",      Command::Cheat(13)),  
-     ("s-Ctrl",      Command::Cheat(14)),  // print board substatus
-     ("9-Ctrl",      Command::Cheat(15)),  // remove second-to-last row
-	 // cheat codes 0-20 are forwarded to the active board, higher codes are handled on the controller in controller_cheat()
-     ("0-Meta",       Command::Cheat(20)),
-     ("1-Meta",       Command::Cheat(21)),
-     ("2-Meta",       Command::Cheat(22)),
-     ("3-Meta",       Command::Cheat(23)),
-     ("4-Meta",       Command::Cheat(24)),
-     ("5-Meta",       Command::Cheat(25)),
-     ("6-Meta",       Command::Cheat(26)),
-     ("7-Meta",       Command::Cheat(27)),
-     ("8-Meta",       Command::Cheat(28)),
-     ("9-Meta",       Command::Cheat(29)),
-];
-
 impl Controller {
-	fn active_id(&self) -> u32 { self.internal.borrow().active }
+	fn active_id(&self, player: usize) -> u32 { self.internal.borrow().players.get(player).map_or(0, |p| p.active) }
+	fn player_count(&self) -> usize { self.internal.borrow().players.len() }
+
+	// the player who controls board ID
+	fn owner(&self, id: u32) -> usize {
+		self.internal.borrow().players.iter().position(|p| p.boards.contains(&id)).unwrap_or(0)
+	}
+
     pub fn initialize(&self, board_count: u32, width: u32, height: u32, preview: bool) {
 		self.set_state(State::Initial);
+		// this seeds the pieces, so it comes before the boards are made. A replay uses the seed it was recorded with.
+		let seed = self.playback.borrow().as_ref().map(|p| p.seed());
+		*self.recorder.borrow_mut() = match seed { Some(seed) => Recorder::replaying(seed), None => Recorder::new(), };
         boards_reset();
         let container = &self.boards_container;
 		while let Some(row) = container.last_child() {
 			container.remove(&row);
 		}
+		let linked = self.linked();
         for i in 0..board_count {
-            let b = Board::new(i, width, height, preview);
+            let b = Board::new(i, width, height, preview, linked);
             container.append(&b);
             boards_add(b);
         }
         self.summary_init(board_count);
         
-		{ self.internal.borrow_mut().score = (0, 0); }
+		{
+			let mut internal = self.internal.borrow_mut();
+			internal.score = (0, 0);
+			internal.seconds = 0;
+			internal.pieces = 0;
+		}
         self.total_points.set_label("0");
         self.total_lines.set_label("0");
-		self.send_command(CMD_SELECT);
+        self.time_disp.set_label("00:00");
+		self.assign_players();
+		self.assign_teams();
+		self.assign_activation();
     }
+
+	pub fn linked(&self) -> Option<LineRule> { self.internal.borrow().linked }
+
+	// the boards are told whether they are linked when they are made, so changing it starts over
+	pub fn set_linked(&self, linked: Option<LineRule>) {
+		if self.linked() == linked { return; }
+		self.internal.borrow_mut().linked = linked;
+		if boards_len() > 0 { self.reinit(); }
+	}
+
+	pub fn set_ramp(&self, lines: u32, minutes: u32) {
+		if self.internal.borrow().ramp == (lines, minutes) { return; }
+		self.internal.borrow_mut().ramp = (lines, minutes);
+		if self.internal.borrow().state == State::Initial { self.assign_activation(); }
+	}
+
+	fn progressive(&self) -> bool {
+		let ramp = self.internal.borrow().ramp;
+		ramp.0 > 0 || ramp.1 > 0
+	}
+
+	// Boards join in the order: each player's first board, then the rest by id. A progressive game starts with just
+	// the first boards, otherwise they are all in play from the start.
+	fn assign_activation(&self) {
+		let order: Vec<u32> = {
+			let internal = self.internal.borrow();
+			let firsts: Vec<u32> = internal.players.iter().filter_map(|p| p.boards.first().copied()).collect();
+			firsts.iter().copied().chain((0..boards_len() as u32).filter(|id| !firsts.contains(id))).collect()
+		};
+		let count = if self.progressive() { self.player_count().clamp(1, order.len()) } else { order.len() };
+		for (i, id) in order.iter().enumerate() {
+			if i < count { board(*id).remove_css_class("inactive"); }
+			else { board(*id).add_css_class("inactive"); }
+		}
+		let mut internal = self.internal.borrow_mut();
+		(internal.activation, internal.active_count) = (order, count);
+	}
+
+	fn is_active(&self, id: u32) -> bool {
+		let internal = self.internal.borrow();
+		internal.activation[..internal.active_count].contains(&id)
+	}
+
+	// Called whenever the lines or the clock change. One board joins for each milestone passed.
+	fn check_milestones(&self) {
+		if !self.progressive() { return; }
+		let target = {
+			let internal = self.internal.borrow();
+			let (lines, minutes) = internal.ramp;
+			// a setting of 0 is off, so checked_div() gives None for it
+			let reached = internal.score.1.checked_div(lines).unwrap_or(0)
+				.max(internal.seconds.checked_div(minutes * 60).unwrap_or(0));
+			(internal.players.len().max(1) + reached as usize).min(internal.activation.len())
+		};
+		while self.internal.borrow().active_count < target { self.activate_next(); }
+	}
+
+	// a board joining a running game gets its own timer from CMD_START, the others are not disturbed
+	fn activate_next(&self) {
+		let (id, running) = {
+			let mut internal = self.internal.borrow_mut();
+			let id = internal.activation[internal.active_count];
+			internal.active_count += 1;
+			(id, internal.state == State::Running)
+		};
+		board(id).remove_css_class("inactive");
+		if running { send_command_to(id, CMD_START); }
+	}
+
+	fn send_command_active(&self, mask: u32) {
+		let active: Vec<u32> = {
+			let internal = self.internal.borrow();
+			internal.activation[..internal.active_count].to_vec()
+		};
+		active.iter().for_each(|id| send_command_to(*id, mask));
+	}
+
+	pub fn set_players(&self, count: u32) {
+		if self.internal.borrow().player_count == count { return; }
+		self.internal.borrow_mut().player_count = count;
+		self.assign_players();
+	}
+
+	// Splits the boards into contiguous blocks, one per player, and gives each player the first board of its block.
+	// With a single player the score line is hidden, the totals already show it.
+	fn assign_players(&self) {
+		let board_count = boards_len() as u32;
+		let player_count = { self.internal.borrow().player_count }.clamp(1, board_count.clamp(1, MAX_PLAYERS));
+		send_command_all(CMD_DESELECT);
+		while let Some(child) = self.players_box.last_child() {
+			self.players_box.remove(&child);
+		}
+		let mut players = Vec::<Player>::new();
+		for p in 0..player_count {
+			let boards: Vec<u32> = (0..board_count).filter(|id| id*player_count/board_count == p).collect();
+			let label = gtk::Label::builder().label(&player_score_string(p as usize, (0, 0))).build();
+			label.add_css_class("score");
+			self.players_box.append(&label);
+			if let Some(first) = boards.first() { send_command_to(*first, CMD_SELECT); }
+			players.push(Player { active: *boards.first().unwrap_or(&0), boards, score: (0, 0), label, });
+		}
+		self.players_box.set_visible(player_count > 1);
+		self.internal.borrow_mut().players = players;
+		self.summary_set_groups();
+	}
+
+	pub fn set_teams(&self, teams: Vec<Team>) {
+		if self.internal.borrow().team_config == teams { return; }
+		self.internal.borrow_mut().team_config = teams;
+		self.assign_teams();
+	}
+
+	// Boards beyond the current board count are dropped from the teams, and teams left without boards are not shown
+	fn assign_teams(&self) {
+		let board_count = boards_len() as u32;
+		while let Some(child) = self.teams_box.last_child() {
+			self.teams_box.remove(&child);
+		}
+		let mut teams = Vec::<TeamScore>::new();
+		for config in self.internal.borrow().team_config.iter() {
+			let team = Team { name: config.name.clone(), boards: config.boards.iter().copied().filter(|id| *id < board_count).collect(), };
+			if team.boards.is_empty() { continue; }
+			let label = gtk::Label::builder().label(&team_score_string(&team.name, (0, 0))).build();
+			label.add_css_class("score");
+			self.teams_box.append(&label);
+			teams.push(TeamScore { team, score: (0, 0), label, });
+		}
+		self.teams_box.set_visible(!teams.is_empty());
+		self.internal.borrow_mut().teams = teams;
+		self.summary_set_groups();
+	}
 	
 	fn reinit(&self) {
 		let rep = board(0).imp();
@@ -285,84 +566,365 @@ impl Controller {
 	}
 
 	pub fn destroy(&self) { self.obj().destroy(); }
+
+	// the boards are made transparent rather than hidden so the window keeps its size
+	fn show_paused(&self, paused: bool) {
+		self.paused_overlay.set_visible(paused);
+		self.boards_container.set_opacity(if paused { 0.0 } else { 1.0 });
+	}
 	
 	fn set_state(&self, state: State) {
 		if self.internal.borrow().state == state { return; }
 		match state {
 			State::Initial => {
 				self.start_button.set_label("Start");
+				self.show_paused(false);
 				self.options_button.show();
+				self.save_button.hide();
+				self.load_button.show();
+				self.load_button.set_sensitive(self.save_file_exists());
 			},
 			State::Paused => {
 				self.options_button.hide();
+				self.save_button.show();
+				self.load_button.hide();
+				self.show_paused(true);
 				self.start_button.set_label("Continue");
 				send_command_all(CMD_STOP);
+				self.recorder.borrow_mut().set_running(false);
 				{ self.internal.borrow().clock.stop(); }
 			},
 			State::Running => {
 				self.options_button.hide();
+				self.save_button.hide();
+				self.load_button.hide();
 				self.start_button.set_label("Pause");
-				send_command_all(CMD_START);
+				self.status_label.set_label(" ");
+				self.show_paused(false);
+				if self.internal.borrow().state == State::Initial { self.recorder.borrow_mut().set_settings(self.replay_settings()); }
+				self.recorder.borrow_mut().set_running(true);
+				self.send_command_active(CMD_START);
 				{ self.internal.borrow().clock.start(); }
 			},
 			State::Finished => {
 				self.options_button.show();
+				self.save_button.hide();
+				self.load_button.show();
+				self.load_button.set_sensitive(self.save_file_exists());
 				send_command_all(CMD_STOP);
 				self.start_button.set_label("New game");
+				self.show_paused(false);
 				{ self.internal.borrow().clock.stop(); }
+				self.save_replay();
+				self.record_statistics();
+				self.check_high_score();
                 self.summary_show();
+				self.write_summary_out();
 			}
 		}
+		// a replay has its own controls instead
+		if self.playing_back() {
+			self.start_button.hide();
+			self.options_button.hide();
+			self.save_button.hide();
+			self.load_button.hide();
+		}
 		// in case Button grabbed it
 		self.obj().grab_focus();
 		self.internal.borrow_mut().state = state;
 	}
 
+	pub fn set_summary_out(&self, file: Option<String>) { self.internal.borrow_mut().summary_out = file; }
+
+	// replays don't overwrite the summary of the last game played. How it went shows in the summary window, open by now.
+	fn write_summary_out(&self) {
+		let Some(file) = self.internal.borrow().summary_out.clone() else { return; };
+		if self.playing_back() { return; }
+		if let Some(summary) = self.internal.borrow().summary.as_ref() { summary.imp().export_reporting(&file); }
+	}
+
+	pub fn set_replay_dir(&self, dir: &str) { self.internal.borrow_mut().replay_dir = dir.to_string(); }
+
+	// called by the boards for every command they carry out
+	pub fn record(&self, id: u32, mask: u32) {
+		if let Ok(mut recorder) = self.recorder.try_borrow_mut() { recorder.record(id, mask); }
+	}
+
+	fn replay_settings(&self) -> ReplaySettings {
+		let rep = board(0).imp();
+		let internal = self.internal.borrow();
+		ReplaySettings { Boards: boards_len() as u32, Width: rep.width(), Height: rep.height(), Preview: rep.show_preview(),
+						 Players: internal.players.len() as u32, Teams: internal.team_config.iter().map(|t| t.to_string()).collect(),
+						 Linked: internal.linked, RampLines: internal.ramp.0, RampMinutes: internal.ramp.1, Focus: internal.focus,
+						 Das: internal.auto_repeat.delay, Arr: internal.auto_repeat.rate, SoftDrop: internal.auto_repeat.soft_drop,
+						 Delay: self.delay.get(), }
+	}
+
+	fn save_replay(&self) {
+		let (dir, score, seconds) = {
+			let internal = self.internal.borrow();
+			(internal.replay_dir.clone(), internal.score, internal.seconds)
+		};
+		let result = self.recorder.borrow_mut().save(&dir, score, seconds);
+		self.show_status(result);
+	}
+
+	// the outcome of saving or loading something, under the buttons until the next game starts. An empty message
+	// leaves what is there.
+	pub fn show_status(&self, result: Result<String, String>) {
+		let message = result.unwrap_or_else(|err| err);
+		if !message.is_empty() { self.status_label.set_label(&message); }
+	}
+
+	pub fn show_high_scores(&self) {
+		let key = highscores::game_key(&self.replay_settings());
+		if let Some(scores) = self.internal.borrow().highscores.as_ref() { scores.imp().show_scores(&key); }
+	}
+
+	pub fn show_statistics(&self) {
+		let key = highscores::game_key(&self.replay_settings());
+		if let Some(stats) = self.internal.borrow().statistics.as_ref() { stats.imp().show_statistics(&key); }
+	}
+
+	// games where no piece landed tell nothing, and replays were counted when they were played
+	fn record_statistics(&self) {
+		if self.playing_back() || self.internal.borrow().pieces == 0 { return; }
+		let settings = self.replay_settings();
+		let internal = self.internal.borrow();
+		let record = GameRecord { Date: replay::now_string(), Time: glib::DateTime::now_local().map_or(0, |d| d.to_unix()),
+								  Game: highscores::game_key(&settings), Settings: settings, Seconds: internal.seconds,
+								  Points: internal.score.0, Lines: internal.score.1,
+								  Boards: internal.summary.as_ref().map(|s| s.imp().counters()).unwrap_or_default(), };
+		if let Err(err) = statistics::append_record(&record) { eprintln!("{}", err); }
+	}
+
+	// a replay's score was already had when it was played
+	fn check_high_score(&self) {
+		if self.playing_back() { return; }
+		let key = highscores::game_key(&self.replay_settings());
+		let internal = self.internal.borrow();
+		if !highscores::qualifies(&highscores::load_scores(), &key, internal.score.0) { return; }
+		let entry = HighScore { Name: String::new(), Points: internal.score.0, Lines: internal.score.1, Seconds: internal.seconds,
+								Date: replay::now_string(), };
+		if let Some(scores) = internal.highscores.as_ref() { scores.imp().ask_name(&key, entry); }
+	}
+
     pub fn board_lost(&self, _board_id: u32) { self.set_state(State::Finished); }
 
     pub fn piece_crashed(&self, id: u32, points: u32, lines: u32, piece_num: u32) {
+		let owner = self.owner(id);
         {
             let mut internal = self.internal.borrow_mut();
             let old_score = internal.score;
             internal.score = (old_score.0 + points, old_score.1 + lines);
+            internal.pieces += 1;
             self.total_points.set_label(&internal.score.0.to_string());
             self.total_lines.set_label(&internal.score.1.to_string());
+			if let Some(player) = internal.players.get_mut(owner) {
+				player.score = (player.score.0 + points, player.score.1 + lines);
+				player.label.set_label(&player_score_string(owner, player.score));
+			}
+			for team in internal.teams.iter_mut().filter(|t| t.team.boards.contains(&id)) {
+				team.score = (team.score.0 + points, team.score.1 + lines);
+				team.label.set_label(&team_score_string(&team.team.name, team.score));
+			}
         }
         self.summary_update(id, points, lines, piece_num);
+		self.check_milestones();
+		if self.focus_policy() == FocusPolicy::Danger { self.follow_danger(owner); }
     }
 
-    pub fn mouse_click(&self, _id: u32, button: u32) { self.do_command(mouse_input(button)); }
+    // MODS is the event's modifier state, as bits of ModifierType
+    pub fn mouse_click(&self, id: u32, button: u32, mods: u32) {
+		let owner = self.owner(id);
+		if self.focus_policy() == FocusPolicy::Click && id != self.active_id(owner) {
+			self.set_board(id);
+			return;
+		}
+		self.do_command(owner, pointer_input(&format!("Mouse{}", button + 1), mods));
+	}
+
+	// the wheel works the same as a click, it goes to the active board of whoever owns the board under the pointer
+	pub fn mouse_wheel(&self, id: u32, down: u32, mods: u32) {
+		let owner = self.owner(id);
+		let name = if down == 0 { "WheelUp" } else { "WheelDown" };
+		self.do_command(owner, pointer_input(name, mods));
+	}
+
+	// Dragging only moves the piece on the board being dragged over, and only if that is its owner's active board
+	pub fn mouse_drag(&self, id: u32, button: u32, column: u32, mods: u32) {
+		let owner = self.owner(id);
+		if self.playing_back() { return; }
+		if pointer_input(&format!("Drag{}", button + 1), mods) != Command::Follow || id != self.active_id(owner) { return; }
+		if !Command::Follow.allowed(&self.internal.borrow().state) { return; }
+		send_command_to(id, CMD_COLUMN | column);
+	}
 
-	fn do_command(&self, command: Command) {
+	// touches act on the board touched, selecting it first if need be. A swipe across several columns repeats.
+	pub fn touch(&self, id: u32, touch: u32, count: u32) {
+		let owner = self.owner(id);
+		if id != self.active_id(owner) && self.focus_policy() != FocusPolicy::Keys { self.set_board(id); }
+		let Some(name) = keymap::TOUCH_NAMES.get(touch as usize) else { return; };
+		let command = self.internal.borrow().keymap.get(name, 1).1;
+		for _ in 0..count { self.do_command(owner, command); }
+	}
+
+	pub fn board_entered(&self, id: u32) {
+		if self.focus_policy() == FocusPolicy::Hover { self.set_board(id); }
+	}
+
+	fn do_command(&self, player: usize, command: Command) {
 		{
+			// nothing the user does reaches the boards of a replay
+			if self.playing_back() { return; }
 			if !command.allowed(&self.internal.borrow().state) { return; }
 			match command {
 				// board commands
-				Command::Left => self.send_command(CMD_LEFT), 
-				Command::Right => self.send_command(CMD_RIGHT),
-				Command::Down => self.send_command(CMD_DOWN),
-				Command::Clockwise => self.send_command(CMD_CLOCKWISE),
-				Command::CounterClockwise => self.send_command(CMD_COUNTERCLOCKWISE),
+				Command::Left => self.send_command(player, CMD_LEFT), 
+				Command::Right => self.send_command(player, CMD_RIGHT),
+				Command::Down => self.send_command(player, CMD_DOWN),
+				Command::Clockwise => self.send_command(player, CMD_CLOCKWISE),
+				Command::CounterClockwise => self.send_command(player, CMD_COUNTERCLOCKWISE),
 				// controller commands
-				Command::Drop => self.send_command(CMD_DROP),
-				Command::Pause => (),
-				Command::Resume => (),
-				Command::TogglePause => (),
-				Command::SetBoard(new_id) => self.set_board(new_id),
+				Command::Drop => self.send_command(player, CMD_DROP),
+				Command::Pause => self.pause(),
+				Command::Resume => self.resume(),
+				Command::TogglePause => { if self.internal.borrow().state == State::Running { self.pause() } else { self.resume() }},
+				Command::SetBoard(index) => self.select_for(player, index),
+				Command::NextBoard => self.cycle_board(player, true),
+				Command::PreviousBoard => self.cycle_board(player, false),
 				Command::Nop => (),
-				Command::Cheat(code) => { if code < 20 {self.send_command(CMD_CHEAT | code)} else { self.controller_cheat(code); }},
+				Command::Follow => (),    // only means something for a drag, see mouse_drag()
+				Command::Macro(index) => self.run_macro(player, index),
+				Command::Cheat(code) => { if code < 20 {self.send_command(player, CMD_CHEAT | code)} else { self.controller_cheat(code); }},
 			}
 		}
 	}
 
+	// makes NEW_ID the active board of the player who owns it. Boards which haven't joined yet can't be chosen.
 	pub fn set_board(&self, new_id: u32) {
-		let old_id = self.active_id();
-		if new_id >= boards_len() as u32 || new_id == old_id { return; }
+		if self.playing_back() { return; }
+		let owner = self.owner(new_id);
+		let old_id = self.active_id(owner);
+		if new_id >= boards_len() as u32 || new_id == old_id || !self.is_active(new_id) { return; }
 		send_command_to(old_id, CMD_DESELECT);
 		send_command_to(new_id, CMD_SELECT);
-		self.internal.borrow_mut().active = new_id;
+		if let Some(player) = self.internal.borrow_mut().players.get_mut(owner) { player.active = new_id; }
+	}
+
+	// board numbers in commands count from the start of the player's own boards
+	fn select_for(&self, player: usize, index: u32) {
+		let id = self.internal.borrow().players.get(player).and_then(|p| p.boards.get(index as usize).copied());
+		if let Some(id) = id { self.set_board(id); }
+	}
+
+	// Next and Previous wrap around at the ends of the player's boards
+	fn cycle_board(&self, player: usize, forward: bool) {
+		let boards: Vec<u32> = match self.internal.borrow().players.get(player) {
+			Some(p) => p.boards.clone(),
+			None => return,
+		};
+		let boards: Vec<u32> = boards.into_iter().filter(|id| self.is_active(*id)).collect();
+		let len = boards.len();
+		if len == 0 { return; }
+		let pos = boards.iter().position(|id| *id == self.active_id(player)).unwrap_or(0);
+		self.set_board(boards[if forward { (pos + 1) % len } else { (pos + len - 1) % len }]);
+	}
+
+	// moves the player to the board whose stack is highest. Ties stay on the current board so the focus doesn't flicker.
+	fn follow_danger(&self, player: usize) {
+		let boards = match self.internal.borrow().players.get(player) {
+			Some(p) => p.boards.clone(),
+			None => return,
+		};
+		let mut target = self.active_id(player);
+		if target >= boards_len() as u32 { return; }
+		let mut highest = board(target).imp().stack_height();
+		for id in boards.into_iter().filter(|id| self.is_active(*id)) {
+			let height = board(id).imp().stack_height();
+			if height > highest { (target, highest) = (id, height); }
+		}
+		self.set_board(target);
+	}
+
+	// Each step is checked on its own by do_command(), so a macro that pauses the game stops there
+	fn run_macro(&self, player: usize, index: u32) {
+		let steps = self.internal.borrow().keymap.macro_steps(index);
+		for step in steps { self.do_command(player, step); }
+	}
+
+	pub fn set_keymap(&self, keymap: Keymap) { self.internal.borrow_mut().keymap = keymap; }
+	pub fn set_auto_repeat(&self, auto_repeat: AutoRepeat) { self.internal.borrow_mut().auto_repeat = auto_repeat; }
+
+	// Movement keys are repeated here rather than by the OS, whose repeats of a held key are ignored
+	fn key_pressed(&self, key: gdk4::Key, code: u32, group: u32, mods: ModifierType) {
+		let name = held_name(code);
+		if self.internal.borrow().held.contains_key(&name) { return; }
+		let (player, command) = keyboard_input(key, code, group, mods);
+		self.input_pressed(name, player, command);
+	}
+
+	// NAME identifies the key or button for repeating, it is released by the same name
+	fn input_pressed(&self, name: String, player: usize, command: Command) {
+		self.do_command(player, command);
+		let timing = self.internal.borrow().auto_repeat.timing(command);
+		if let Some((delay, interval)) = timing {
+			let serial = {
+				let mut internal = self.internal.borrow_mut();
+				internal.held_serial += 1;
+				let serial = internal.held_serial;
+				internal.held.insert(name.clone(), serial);
+				serial
+			};
+			start_repeat(name, serial, player, command, delay, interval);
+		}
 	}
 
+	fn key_released(&self, code: u32) { self.internal.borrow_mut().held.remove(&held_name(code)); }
+
+	// Gamepad buttons go through the keymap like keys, a binding for the numbered pad first and then one for any pad
+	pub fn pad_event(&self, event: gamepad::PadEvent) {
+		match event {
+			gamepad::PadEvent::Button { name, pressed: true } => {
+				let players = self.player_count();
+				let mut found = self.internal.borrow().keymap.get(&name, players);
+				if found.1 == Command::Nop {
+					if let Some(any) = gamepad::any_pad_key(&name) { found = self.internal.borrow().keymap.get(&any, players); }
+				}
+				self.input_pressed(name, found.0, found.1);
+			},
+			gamepad::PadEvent::Button { name, pressed: false } => { self.internal.borrow_mut().held.remove(&name); },
+			// pads come and go without anything to do here, their buttons are looked up as they are pressed
+			gamepad::PadEvent::Connected { .. } | gamepad::PadEvent::Disconnected { .. } => (),
+		}
+	}
+
+	// minimizing the window also makes it inactive, so this covers both
+	fn deactivated(&self) {
+		self.internal.borrow_mut().held.clear();
+		if self.internal.borrow().auto_pause { self.pause(); }
+	}
+
+	pub fn set_auto_pause(&self, auto_pause: bool) { self.internal.borrow_mut().auto_pause = auto_pause; }
+
+	// Takes effect the next time a piece falls. The boards speed up from this as lines are completed.
+	pub fn set_delay(&self, seconds: f64) { self.delay.set(seconds); }
+
+	pub fn delay_ms(&self) -> u32 { (self.delay.get() * 1000.0) as u32 }
+
+	fn pause(&self) {
+		if self.internal.borrow().state == State::Running { self.set_state(State::Paused); }
+	}
+
+	// Resume also starts a game that hasn't begun, the same as the Start button
+	fn resume(&self) {
+		let state = self.internal.borrow().state;
+		if state == State::Paused || state == State::Initial { self.set_state(State::Running); }
+	}
+
+	pub fn focus_policy(&self) -> FocusPolicy { self.internal.borrow().focus }
+	pub fn set_focus_policy(&self, policy: FocusPolicy) { self.internal.borrow_mut().focus = policy; }
+
 	fn controller_cheat(&self, code: u32) {
 		match code {
             21 => self.summary_show(),
@@ -370,12 +932,8 @@ impl Controller {
 		}
 	}
 
-	fn send_command(&self, mask: u32) {
-		let id = self.active_id();
-		if id < boards_len() as u32 {
-			let id_u32 = self.active_id();
-			board(id).emit_by_name::<()>("board-command", &[&id_u32, &mask, ]);
-		}
+	fn send_command(&self, player: usize, mask: u32) {
+		if player < self.player_count() { send_command_to(self.active_id(player), mask); }
 	}
 
 	fn options(show: bool) {
@@ -384,10 +942,13 @@ impl Controller {
 	}
 
 	fn tick(&self) {
-        let mut internal = self.internal.borrow_mut();
-		internal.seconds += 1;
-		let time_str = format!("{:02}:{:02}", internal.seconds/60, internal.seconds % 60);
-		self.time_disp.set_label(&time_str);
+		{
+			let mut internal = self.internal.borrow_mut();
+			internal.seconds += 1;
+			let time_str = format!("{:02}:{:02}", internal.seconds/60, internal.seconds % 60);
+			self.time_disp.set_label(&time_str);
+		}
+		self.check_milestones();
 	}
 
     // accessors for Summary: I'd like to have a single accessor to the object summary(), but can't figure out how to
@@ -401,13 +962,35 @@ impl Controller {
         let internal = self.internal.borrow();
         internal.summary.as_ref().unwrap().imp().initialize(count);
     }
+    // subtotal rows: one per player if there is more than one, then one per team
+    fn summary_set_groups(&self) {
+        let internal = self.internal.borrow();
+        let mut groups = Vec::<(String, Vec<u32>)>::new();
+        if internal.players.len() > 1 {
+            internal.players.iter().enumerate().for_each(|(i, p)| groups.push((format!("Player {}", i + 1), p.boards.clone())));
+        }
+        internal.teams.iter().for_each(|t| groups.push((t.team.name.clone(), t.team.boards.clone())));
+        internal.summary.as_ref().unwrap().imp().set_groups(groups);
+    }
     fn summary_show(&self) {
+        let settings = self.replay_settings();
         let internal = self.internal.borrow();
+        internal.summary.as_ref().unwrap().imp().set_game(internal.seconds, settings);
         internal.summary.as_ref().unwrap().imp().build_display();
         internal.summary.as_ref().unwrap().show();
     }
 }
 
+// Boards report their commands here. The first boards are made before the controller is registered, they have
+// nothing to record yet.
+pub fn record(id: u32, mask: u32) { if has_instance() { controller().record(id, mask); } }
+
+// the boards' timers don't run in a replay, the ticks they made are in it
+pub fn playing_back() -> bool { has_instance() && controller().playing_back() }
+
+// the boards' starting gravity, 0 for their own default. The first boards are made before there is a setting.
+pub fn starting_delay_ms() -> u32 { if has_instance() { controller().delay_ms() } else { 0 } }
+
 fn send_command_all(mask: u32) { for id in 0..boards_len() as u32 {send_command_to(id, mask); } }
 
 fn send_command_to(id: u32, mask: u32) {
@@ -416,47 +999,59 @@ fn send_command_to(id: u32, mask: u32) {
     }
 }
 
+fn player_score_string(player: usize, score: (u32, u32)) -> String {
+	format!("Player {}: {} points, {} lines    ", player + 1, score.0, score.1)
+}
+
+fn team_score_string(name: &str, score: (u32, u32)) -> String {
+	format!("{}: {} points, {} lines    ", name, score.0, score.1)
+}
+
 
 //////////////////////////////////////////////////////////////////
 //
 // Handle user input
 //
-// Keeps track of the modifier keys and maps input to commands
+// Maps input to commands. The modifiers come from the event itself, so there is nothing to get out of step when a
+// release goes to another window.
 //
 //////////////////////////////////////////////////////////////////
-fn mouse_input(button: u32) -> Command {
-    let button_string = modifier_bits_string(format!("Mouse{}", button + 1));
-    command_map_get(&button_string)
-}
 
-fn keyboard_input(key: gdk4::Key) -> Command {
-    let key_string = modifier_bits_string(key.to_lower().name().unwrap().to_string());
-//    println!("{:#?}", key_string);
-    command_map_get(&key_string)
+// mouse buttons, the wheel and drags all look up their name with the modifiers held
+fn pointer_input(name: &str, mods: u32) -> Command {
+    let button_string = keymap::key_string(name, ModifierType::from_bits_truncate(mods));
+    controller_inst().internal.borrow().keymap.get(&button_string, 1).1
 }
 
-fn modifier_bits_string(mut key: String) -> String {
-	let bits = controller_inst().internal.borrow().modifier_bits;
-	if bits & ModifierType::SHIFT_MASK.bits() != 0 { key.push_str("-Shift"); }
-	if bits & ModifierType::ALT_MASK.bits() != 0 { key.push_str("-Alt"); }
-	if bits & ModifierType::CONTROL_MASK.bits() != 0 { key.push_str("-Ctrl"); }
-	if bits & ModifierType::META_MASK.bits() != 0 { key.push_str("-Meta"); }
-    key
+// Returns the player the key belongs to as well as the command. A binding for the key's name on the current layout
+// wins over one for its position, so users' own bindings beat the defaults, which are mostly positions.
+fn keyboard_input(key: gdk4::Key, code: u32, group: u32, mods: ModifierType) -> (usize, Command) {
+    let players = controller_inst().player_count();
+    let internal = controller_inst().internal.borrow();
+    let found = internal.keymap.get(&keymap::key_string(&keymap::base_key_name(key, code, group), mods), players);
+    if found.1 != Command::Nop { return found; }
+    match keymap::physical_name(code) {
+        Some(name) => internal.keymap.get(&keymap::key_string(&name, mods), players),
+        None => found,
+    }
 }
 
-// Alt doesn't work well, it changes the key. Maybe I should work with codes, but that would make customization harder
-fn set_modifier(key: gdk4::Key, pressed: bool) {
-	let name = key.to_lower().name().unwrap().to_string();
-	let mask = match &name[..] {
-		"Shift_L"   | "Shift_R"   => ModifierType::SHIFT_MASK.bits(),
-		"Control_L" | "Control_R" => ModifierType::CONTROL_MASK.bits(),
-		"Alt_L"     | "Alt_R"     => ModifierType::ALT_MASK.bits(),
-		"Meta_L"    | "Meta_R"    => ModifierType::META_MASK.bits(),
-		_ => return,
-	};
-	let mut internal = controller_inst().internal.borrow_mut();
-	if pressed { internal.modifier_bits  |= mask; }
-	else { internal.modifier_bits &= !mask; }
+// held keys are tracked by keycode so that pressing a modifier while moving doesn't stop the repeat
+fn held_name(code: u32) -> String { format!("#{}", code) }
+
+// Runs COMMAND again after DELAY and then every INTERVAL until the key is released. A released key is removed from
+// HELD, and a key pressed again gets a new serial number, either way the old timer finds it is stale and stops.
+fn start_repeat(name: String, serial: u32, player: usize, command: Command, delay: u32, interval: u32) {
+	let still_held = move || controller_inst().internal.borrow().held.get(&name) == Some(&serial);
+	glib::timeout_add_local_once(core::time::Duration::from_millis(delay as u64), move || {
+		if !still_held() { return; }
+		controller_inst().do_command(player, command);
+		glib::timeout_add_local(core::time::Duration::from_millis(interval as u64), move || {
+			if !still_held() { return glib::Continue(false); }
+			controller_inst().do_command(player, command);
+			glib::Continue(true)
+		});
+	});
 }
 
 #[derive(Debug, Default)]
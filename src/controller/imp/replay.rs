@@ -0,0 +1,380 @@
+// Replays: a record of everything that happened to the boards during one game
+//
+// The pieces come from fastrand, which is seeded at the start of each game, so the seed together with every command
+// each board received, in order, is enough to play the game again exactly. Commands are recorded where the boards
+// carry them out (Board::do_command) rather than where they come from, which catches the gravity ticks from the
+// boards' own timers and the SELECT/DESELECT of board switches along with the player's moves.
+//
+// A replay is a yaml file. Events are written one to a line as "MS BOARD MASK", the time being milliseconds of play
+// since the game started, not counting pauses, and the mask being one of the board::imp::CMD_* values:
+//
+//     Version: 1
+//     Seed: 1234567890
+//     Date: 2026-10-19 20:15:02
+//     Settings:
+//       Boards: 2
+//       ...
+//     Events:
+//     - 0 0 6
+//     - 512 0 3
+//
+// Finished games are saved in the replay directory, an empty directory name turns recording off.
+//
+// `tetrii --replay FILE` shows one. The boards are set up again with the recorded seed and the events are fed to them
+// at their recorded times, scaled by the chosen speed. Nothing the user does reaches the boards, and their own timers
+// don't run, see Controller::playing_back(). Going backwards starts again from the beginning and replays everything up
+// to that point at once, which is quick since there is nothing to wait for.
+
+use crate::config::{Config, Team, DELAY_DEFAULT};
+use crate::board::imp::LineRule;
+use super::{Controller, FocusPolicy, State, boards_len, controller, send_command_to};
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use gtk::glib;
+use gtk::glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const REPLAY_VERSION: u32 = 1;
+
+// The rules of the game, named the same as in the config file. Enough to set up the same boards again.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReplaySettings {
+    pub Boards:      u32,
+    pub Width:       u32,
+    pub Height:      u32,
+    pub Preview:     bool,
+    pub Players:     u32,
+    pub Teams:       Vec<String>,
+    pub Linked:      Option<LineRule>,
+    pub RampLines:   u32,
+    pub RampMinutes: u32,
+    pub Focus:       FocusPolicy,
+    pub Das:         u32,
+    pub Arr:         u32,
+    pub SoftDrop:    u32,
+    #[serde(default)]
+    pub Delay:       f64,               // missing from older files, which all used the default
+}
+
+impl ReplaySettings {
+    pub fn teams(&self) -> Vec<Team> { self.Teams.iter().filter_map(|t| t.parse::<Team>().ok()).collect() }
+
+    // what the replay needs to be shown the way it was played
+    pub fn apply_to(&self, config: &mut Config) {
+        (config.boards, config.width, config.height, config.preview) = (self.Boards, self.Width, self.Height, self.Preview);
+        (config.players, config.teams, config.linked) = (self.Players, self.teams(), self.Linked);
+        (config.ramp_lines, config.ramp_minutes, config.focus) = (self.RampLines, self.RampMinutes, self.Focus);
+        (config.das, config.arr, config.soft_drop) = (self.Das, self.Arr, self.SoftDrop);
+        config.delay = self.delay();
+    }
+
+    pub fn delay(&self) -> f64 { if self.Delay > 0.0 { self.Delay } else { DELAY_DEFAULT } }
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Replay {
+    pub Version:  u32,
+    pub Seed:     u64,
+    pub Date:     String,
+    pub Settings: ReplaySettings,
+    pub Score:    (u32, u32),    // (points, lines) at the end, for looking through replays without playing them
+    pub Seconds:  u32,
+    pub Events:   Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn load(filename: &str) -> Result<Replay, String> {
+        let text = std::fs::read_to_string(filename).map_err(|err| format!("Could not read replay {}: {}", filename, err))?;
+        let replay: Replay = serde_yaml::from_str(&text).map_err(|err| format!("Error parsing replay {}: {}", filename, err))?;
+        if replay.Version > REPLAY_VERSION {
+            return Err(format!("Replay {} is version {}, this only reads up to version {}", filename, replay.Version, REPLAY_VERSION));
+        }
+        Ok(replay)
+    }
+}
+
+// the local time, for stamping files
+pub fn now_string() -> String {
+    glib::DateTime::now_local().ok().and_then(|d| d.format("%Y-%m-%d %H:%M:%S").ok()).map(|s| s.to_string()).unwrap_or_default()
+}
+
+// one command carried out by a board
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ReplayEvent {
+    pub ms:    u64,
+    pub board: u32,
+    pub mask:  u32,
+}
+
+impl fmt::Display for ReplayEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{} {} {}", self.ms, self.board, self.mask) }
+}
+
+impl FromStr for ReplayEvent {
+    type Err = String;
+    fn from_str(desc: &str) -> Result<ReplayEvent, String> {
+        let fields: Vec<&str> = desc.split_whitespace().collect();
+        let bad = || format!("replay event `{}` should be MS BOARD MASK", desc);
+        if fields.len() != 3 { return Err(bad()); }
+        Ok(ReplayEvent { ms: fields[0].parse().map_err(|_| bad())?,
+                         board: fields[1].parse().map_err(|_| bad())?,
+                         mask: fields[2].parse().map_err(|_| bad())?, })
+    }
+}
+
+impl TryFrom<String> for ReplayEvent {
+    type Error = String;
+    fn try_from(desc: String) -> Result<ReplayEvent, String> { desc.parse() }
+}
+
+impl From<ReplayEvent> for String {
+    fn from(event: ReplayEvent) -> String { event.to_string() }
+}
+
+// Collects the replay of the game in progress. The clock only runs while the game does, so a long pause doesn't
+// leave a gap in the replay.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    replay: Replay,
+    recording: bool,            // false before the game is set up and after it has been saved
+    elapsed: i64,               // microseconds of play before RUNNING_SINCE
+    running_since: Option<i64>,
+}
+
+impl Recorder {
+    // Seeds the piece generator, so it must be called before the boards pick their first pieces
+    pub fn new() -> Recorder {
+        let seed = fastrand::u64(..);
+        fastrand::seed(seed);
+        Recorder { replay: Replay { Version: REPLAY_VERSION, Seed: seed, ..Default::default() }, recording: true, ..Default::default() }
+    }
+
+    // for showing a replay: the pieces come out the same as when it was recorded, and nothing is recorded again
+    pub fn replaying(seed: u64) -> Recorder {
+        fastrand::seed(seed);
+        Recorder::default()
+    }
+
+    pub fn seed(&self) -> u64 { self.replay.Seed }
+
+    // the settings can change between setting up the boards and starting, so they are taken when the game starts
+    pub fn set_settings(&mut self, settings: ReplaySettings) {
+        if self.replay.Date.is_empty() { self.replay.Date = now_string(); }
+        self.replay.Settings = settings;
+    }
+
+    pub fn record(&mut self, board: u32, mask: u32) {
+        if !self.recording { return; }
+        let ms = (self.play_time() / 1000) as u64;
+        self.replay.Events.push(ReplayEvent { ms, board, mask, });
+    }
+
+    // starts and stops the clock
+    pub fn set_running(&mut self, running: bool) {
+        let now = glib::monotonic_time();
+        match (running, self.running_since) {
+            (true, None) => self.running_since = Some(now),
+            (false, Some(since)) => {
+                self.elapsed += now - since;
+                self.running_since = None;
+            },
+            _ => (),
+        }
+    }
+
+    fn play_time(&self) -> i64 { self.elapsed + self.running_since.map_or(0, |since| glib::monotonic_time() - since) }
+
+    // Writes the replay to DIR and stops recording. Games which never started aren't worth keeping.
+    pub fn save(&mut self, dir: &str, score: (u32, u32), seconds: u32) -> Result<String, String> {
+        self.set_running(false);
+        self.recording = false;
+        if dir.is_empty() || self.replay.Date.is_empty() { return Ok(String::new()); }
+        self.replay.Score = score;
+        self.replay.Seconds = seconds;
+        std::fs::create_dir_all(dir).map_err(|err| format!("Could not make replay directory {}: {}", dir, err))?;
+        let stamp = self.replay.Date.replace([' ', ':'], "-");
+        let mut path = PathBuf::from(dir);
+        path.push(format!("tetrii-{}.yaml", stamp));
+        let filename = path.to_string_lossy().to_string();
+        let text = serde_yaml::to_string(&self.replay).map_err(|err| format!("Error writing replay {}: {}", filename, err))?;
+        std::fs::write(&path, text).map_err(|err| format!("Error writing replay {}: {}", filename, err))?;
+        Ok(format!("Saved replay {}", filename))
+    }
+}
+
+//////////////////////////////////////////////////////////////////
+//
+// Showing a replay
+//
+//////////////////////////////////////////////////////////////////
+
+// the choices in the speed dropdown
+const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const SPEED_DEFAULT: u32 = 2;
+
+#[derive(Debug, Default)]
+pub struct Playback {
+    replay: Replay,
+    position: usize,     // index of the next event to carry out
+    speed: f64,
+    playing: bool,
+    serial: u32,         // changed whenever the timer should stop, a stale timer finds it out of date and quits
+}
+
+impl Playback {
+    pub fn seed(&self) -> u64 { self.replay.Seed }
+    fn next(&self) -> Option<ReplayEvent> { self.replay.Events.get(self.position).copied() }
+    // the time of the last event carried out
+    fn time(&self) -> u64 { if self.position == 0 { 0 } else { self.replay.Events[self.position - 1].ms } }
+    fn length(&self) -> u64 { self.replay.Events.last().map_or(0, |e| e.ms) }
+}
+
+impl Controller {
+    pub fn playing_back(&self) -> bool { self.playback.try_borrow().map_or(true, |p| p.is_some()) }
+
+    pub fn start_playback(&self, replay: Replay) {
+        let length = replay.Events.last().map_or(0, |e| e.ms);
+        *self.playback.borrow_mut() = Some(Playback { replay, speed: SPEEDS[SPEED_DEFAULT as usize], ..Default::default() });
+        self.obj().set_title(Some("Tetrii replay"));
+        self.start_button.hide();
+        self.options_button.hide();
+        self.save_button.hide();
+        self.load_button.hide();
+        self.replay_bar.set_visible(true);
+        self.replay_position.set_range(0.0, length.max(1) as f64);
+        self.replay_speed_widget.set_selected(SPEED_DEFAULT);
+        self.replay_play_button.connect_clicked(|_| {
+            let playing = controller().playback.borrow().as_ref().is_some_and(|p| p.playing);
+            controller().set_playing(!playing);
+        });
+        self.replay_step_button.connect_clicked(|_| controller().step_piece());
+        self.replay_speed_widget.connect_selected_notify(|widget| controller().set_speed(widget.selected()));
+        // change-value is only sent for the user moving the slider, not for set_value()
+        self.replay_position.connect_change_value(clone!(@weak self as this => @default-return gtk::Inhibit(false), move |_, _, value| {
+            this.seek(value.max(0.0) as u64);
+            gtk::Inhibit(false)
+        }));
+        self.rewind();
+    }
+
+    // sets the boards up as they were at the start, initialize() seeds the pieces from the replay
+    fn rewind(&self) {
+        let settings = match self.playback.borrow().as_ref() {
+            Some(playback) => playback.replay.Settings.clone(),
+            None => return,
+        };
+        self.initialize(settings.Boards, settings.Width, settings.Height, settings.Preview);
+        if let Some(playback) = self.playback.borrow_mut().as_mut() { playback.position = 0; }
+        self.show_playback_time(0);
+    }
+
+    // carries out the next event, returns its time or None at the end. The borrow is let go first, the boards report
+    // back to the controller while they work.
+    fn playback_event(&self) -> Option<u64> {
+        let event = {
+            let mut playback = self.playback.borrow_mut();
+            let playback = playback.as_mut()?;
+            let event = playback.next()?;
+            playback.position += 1;
+            event
+        };
+        if event.board < boards_len() as u32 { send_command_to(event.board, event.mask); }
+        Some(event.ms)
+    }
+
+    // carries out every event up to and including time MS
+    fn playback_until(&self, ms: u64) {
+        loop {
+            let next = self.playback.borrow().as_ref().and_then(|p| p.next());
+            match next {
+                Some(event) if event.ms <= ms => { self.playback_event(); },
+                _ => break,
+            }
+        }
+    }
+
+    fn set_playing(&self, playing: bool) {
+        let at_end = {
+            let mut playback = self.playback.borrow_mut();
+            let Some(playback) = playback.as_mut() else { return; };
+            playback.serial += 1;
+            playback.playing = playing && playback.next().is_some();
+            playing && !playback.playing
+        };
+        if at_end { return self.replay_play_button.set_label("Play"); }
+        self.replay_play_button.set_label(if playing { "Pause" } else { "Play" });
+        if playing { self.schedule_playback(); }
+    }
+
+    // waits until the next event is due, then carries out everything due at that moment and waits again
+    fn schedule_playback(&self) {
+        let next = self.playback.borrow().as_ref().map(|playback| {
+            playback.next().map(|event| (playback.serial, event.ms, ((event.ms - playback.time()) as f64 / playback.speed) as u64))
+        });
+        let (serial, due, wait) = match next {
+            Some(Some(next)) => next,
+            Some(None) => return self.set_playing(false),
+            None => return,
+        };
+        glib::timeout_add_local_once(core::time::Duration::from_millis(wait), move || {
+            let this = controller();
+            if this.playback.borrow().as_ref().map(|p| p.serial) != Some(serial) { return; }
+            this.playback_until(due);
+            this.show_playback_time(due);
+            this.schedule_playback();
+        });
+    }
+
+    fn set_speed(&self, index: u32) {
+        let playing = {
+            let mut playback = self.playback.borrow_mut();
+            let Some(playback) = playback.as_mut() else { return; };
+            playback.speed = SPEEDS[(index as usize).min(SPEEDS.len() - 1)];
+            playback.playing
+        };
+        // the wait for the next event was worked out at the old speed
+        if playing { self.set_playing(true); }
+    }
+
+    // stops, then goes on until the next piece lands on any board
+    fn step_piece(&self) {
+        self.set_playing(false);
+        let pieces = self.internal.borrow().pieces;
+        while self.internal.borrow().pieces == pieces && self.internal.borrow().state != State::Finished {
+            if self.playback_event().is_none() { break; }
+        }
+        let time = self.playback.borrow().as_ref().map_or(0, |p| p.time());
+        self.show_playback_time(time);
+    }
+
+    // jumps to time MS, keeping on playing if it was
+    fn seek(&self, ms: u64) {
+        let (playing, time) = match self.playback.borrow().as_ref() {
+            Some(playback) => (playback.playing, playback.time()),
+            None => return,
+        };
+        self.set_playing(false);
+        if ms < time { self.rewind(); }
+        self.playback_until(ms);
+        self.show_playback_time(ms);
+        if playing { self.set_playing(true); }
+    }
+
+    // the clock shows the replay's time, and boards that joined on the clock join again
+    fn show_playback_time(&self, ms: u64) {
+        let length = self.playback.borrow().as_ref().map_or(0, |p| p.length());
+        let seconds = (ms.min(length) / 1000) as u32;
+        self.internal.borrow_mut().seconds = seconds;
+        self.time_disp.set_label(&format!("{:02}:{:02}", seconds / 60, seconds % 60));
+        self.replay_position.set_value(ms as f64);
+        self.check_milestones();
+    }
+}
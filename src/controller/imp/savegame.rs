@@ -0,0 +1,140 @@
+// Saving a game to carry on with later
+//
+// Only a paused game can be saved. The file has the rules the game was set up with, the controller's scores and clock,
+// the summary counts and each board's state (see board::imp::BoardState). Loading sets the boards up the same way,
+// puts everything back and leaves the game paused, so Continue carries on where it stopped.
+//
+// The pieces still to come can't be saved, so a game that has been loaded gets no replay.
+
+use super::{Controller, State, board, boards_len, player_score_string, team_score_string};
+use super::replay::{Recorder, ReplaySettings, now_string};
+use crate::board::imp::BoardState;
+use crate::config::Config;
+
+use clap::Parser;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const SAVE_VERSION: u32 = 1;
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SavedPlayer {
+    pub Active: u32,
+    pub Score:  (u32, u32),
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SavedGame {
+    pub Version:     u32,
+    pub Date:        String,
+    pub Settings:    ReplaySettings,
+    pub Seconds:     u32,
+    pub Score:       (u32, u32),
+    pub Pieces:      u32,
+    pub ActiveCount: usize,             // how many boards of a progressive game had joined
+    pub Players:     Vec<SavedPlayer>,
+    pub Teams:       Vec<(u32, u32)>,
+    pub Summary:     Vec<[u32; 9]>,
+    pub Boards:      Vec<BoardState>,
+}
+
+impl Controller {
+    pub fn set_save_file(&self, file: &str) { self.internal.borrow_mut().save_file = file.to_string(); }
+
+    pub(super) fn save_file_exists(&self) -> bool { std::path::Path::new(&self.internal.borrow().save_file).exists() }
+
+    // the buttons report how it went under them, see show_status()
+    pub fn save_clicked(&self) {
+        let result = self.save_game();
+        self.show_status(result);
+        self.load_button.set_sensitive(self.save_file_exists());
+    }
+
+    pub fn load_clicked(&self) {
+        let result = self.load_game();
+        self.show_status(result);
+    }
+
+    pub fn save_game(&self) -> Result<String, String> {
+        if self.internal.borrow().state != State::Paused { return Err("Only a paused game can be saved".to_string()); }
+        let boards: Vec<BoardState> = (0..boards_len() as u32).map(|id| board(id).imp().snapshot()).collect();
+        let summary = self.internal.borrow().summary.as_ref().map(|s| s.imp().counters()).unwrap_or_default();
+        let settings = self.replay_settings();
+        let internal = self.internal.borrow();
+        let saved = SavedGame { Version: SAVE_VERSION, Date: now_string(), Settings: settings, Seconds: internal.seconds,
+                                Score: internal.score, Pieces: internal.pieces, ActiveCount: internal.active_count,
+                                Players: internal.players.iter().map(|p| SavedPlayer { Active: p.active, Score: p.score, }).collect(),
+                                Teams: internal.teams.iter().map(|t| t.score).collect(),
+                                Summary: summary, Boards: boards, };
+        let filename = &internal.save_file;
+        let text = serde_yaml::to_string(&saved).map_err(|err| format!("Error writing saved game {}: {}", filename, err))?;
+        std::fs::write(filename, text).map_err(|err| format!("Error writing saved game {}: {}", filename, err))?;
+        Ok(format!("Saved game to {}", filename))
+    }
+
+    pub fn load_game(&self) -> Result<String, String> {
+        let state = self.internal.borrow().state;
+        if state == State::Running || state == State::Paused { return Err("Can't load a game while one is being played".to_string()); }
+        let filename = self.internal.borrow().save_file.clone();
+        let text = std::fs::read_to_string(&filename).map_err(|err| format!("Could not read saved game {}: {}", filename, err))?;
+        let saved: SavedGame = serde_yaml::from_str(&text).map_err(|err| format!("Error parsing saved game {}: {}", filename, err))?;
+        if saved.Version > SAVE_VERSION {
+            return Err(format!("Saved game {} is version {}, this only reads up to version {}", filename, saved.Version, SAVE_VERSION));
+        }
+        let settings = &saved.Settings;
+        // the same checks as the config gets, so a hand edited file can't make boards that don't work
+        let mut config = Config::try_parse_from(["tetrii"]).map_err(|err| err.to_string())?;
+        settings.apply_to(&mut config);
+        config.check_values();
+        if let Some(problem) = config.problems.first() {
+            return Err(format!("Error in saved game {}: {}", filename, problem.message));
+        }
+        if saved.Boards.len() != settings.Boards as usize {
+            return Err(format!("Saved game {} has {} boards, expected {}", filename, saved.Boards.len(), settings.Boards));
+        }
+        for (id, state) in saved.Boards.iter().enumerate() {
+            state.check(id as u32, settings.Boards, settings.Width, settings.Height, settings.Linked.is_some())
+                .map_err(|err| format!("Error in saved game {}: {}", filename, err))?;
+        }
+
+        // the same setup as when it was saved
+        self.set_players(settings.Players);
+        self.set_teams(settings.teams());
+        self.set_linked(settings.Linked);
+        self.set_ramp(settings.RampLines, settings.RampMinutes);
+        self.set_focus_policy(settings.Focus);
+        self.set_delay(settings.delay());
+        self.initialize(settings.Boards, settings.Width, settings.Height, settings.Preview);
+        for (id, state) in saved.Boards.iter().enumerate() {
+            if let Err(err) = board(id as u32).imp().restore(state) {
+                self.reinit();
+                return Err(format!("Error in saved game {}: {}", filename, err));
+            }
+        }
+        while self.internal.borrow().active_count < saved.ActiveCount.min(boards_len()) { self.activate_next(); }
+        for player in saved.Players.iter() { self.set_board(player.Active); }
+
+        {
+            let mut internal = self.internal.borrow_mut();
+            (internal.score, internal.seconds, internal.pieces) = (saved.Score, saved.Seconds, saved.Pieces);
+            self.total_points.set_label(&internal.score.0.to_string());
+            self.total_lines.set_label(&internal.score.1.to_string());
+            self.time_disp.set_label(&format!("{:02}:{:02}", saved.Seconds / 60, saved.Seconds % 60));
+            for (i, (player, saved)) in internal.players.iter_mut().zip(saved.Players.iter()).enumerate() {
+                player.score = saved.Score;
+                player.label.set_label(&player_score_string(i, player.score));
+            }
+            for (team, score) in internal.teams.iter_mut().zip(saved.Teams.iter()) {
+                team.score = *score;
+                team.label.set_label(&team_score_string(&team.team.name, team.score));
+            }
+            if let Some(summary) = internal.summary.as_ref() { summary.imp().set_counters(&saved.Summary); }
+        }
+        *self.recorder.borrow_mut() = Recorder::default();
+        self.set_state(State::Paused);
+        Ok(format!("Loaded game from {}, saved {}", filename, saved.Date))
+    }
+}
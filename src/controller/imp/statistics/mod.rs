@@ -0,0 +1,232 @@
+// Statistics from every game played, kept in the user's data directory (~/.local/share/tetrii/history.yaml on Linux)
+//
+// Each finished game is added to the end of the file as a yaml document of its own, so saving a game never needs the
+// earlier ones read or rewritten. The window adds them up, for all games or one setup, overall and week by week.
+
+use crate::controller::imp::replay::ReplaySettings;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use gtk::{gio, glib};
+use serde::{Deserialize, Serialize};
+
+glib::wrapper! {
+    pub struct Statistics(ObjectSubclass<imp::Statistics>)
+    @extends gtk::Widget, gtk::Window, gtk::ApplicationWindow, @implements gio::ActionMap, gio::ActionGroup;
+}
+
+impl Statistics {
+    pub fn new<P: glib::IsA<gtk::Application>>(app: &P, ) -> Self {
+		glib::Object::builder().property("application", app).build()
+	}
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GameRecord {
+    pub Date:     String,
+    pub Time:     i64,                  // the same as DATE in seconds since 1970, for sorting out the weeks
+    pub Game:     String,               // see highscores::game_key()
+    pub Settings: ReplaySettings,
+    pub Seconds:  u32,
+    pub Points:   u32,
+    pub Lines:    u32,
+    pub Boards:   Vec<[u32; 9]>,        // the summary counts for each board: points, lines and then each piece
+}
+
+impl GameRecord {
+    fn pieces(&self) -> u32 { self.Boards.iter().map(|b| b[2..].iter().sum::<u32>()).sum() }
+}
+
+fn history_file() -> Option<PathBuf> { dirs::data_dir().map(|dir| dir.join("tetrii").join("history.yaml")) }
+
+pub fn append_record(record: &GameRecord) -> Result<(), String> {
+    let path = history_file().ok_or("No data directory for the statistics".to_string())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("Could not make directory {}: {}", dir.display(), err))?;
+    }
+    let text = serde_yaml::to_string(record).map_err(|err| format!("Error writing statistics {}: {}", path.display(), err))?;
+    let mut file = std::fs::OpenOptions::new().append(true).create(true).open(&path)
+        .map_err(|err| format!("Could not open statistics {}: {}", path.display(), err))?;
+    file.write_all(format!("---\n{}", text).as_bytes()).map_err(|err| format!("Error writing statistics {}: {}", path.display(), err))
+}
+
+// games that can't be read are reported and left out, the rest still count
+pub fn load_history() -> Vec<GameRecord> {
+    let Some(path) = history_file() else { return Vec::new(); };
+    let Ok(text) = std::fs::read_to_string(&path) else { return Vec::new(); };
+    let mut records = Vec::<GameRecord>::new();
+    for document in serde_yaml::Deserializer::from_str(&text) {
+        match GameRecord::deserialize(document) {
+            Ok(record) => records.push(record),
+            Err(err) => eprintln!("Error in statistics {}: {}", path.display(), err),
+        }
+    }
+    records
+}
+
+// the sums over a set of games, and the best of them
+#[derive(Debug, Default)]
+struct Totals {
+    games: u32,
+    seconds: u64,
+    points: u64,
+    lines: u64,
+    pieces: u64,
+    best: Option<(u32, String)>,    // (points, date)
+}
+
+impl Totals {
+    fn add(&mut self, record: &GameRecord) {
+        self.games += 1;
+        self.seconds += record.Seconds as u64;
+        self.points += record.Points as u64;
+        self.lines += record.Lines as u64;
+        self.pieces += record.pieces() as u64;
+        if self.best.as_ref().is_none_or(|(points, _)| record.Points > *points) { self.best = Some((record.Points, record.Date.clone())); }
+    }
+
+    fn average(&self, sum: u64) -> f64 { if self.games == 0 { 0.0 } else { sum as f64 / self.games as f64 } }
+
+    fn per_minute(&self) -> f64 { if self.seconds == 0 { 0.0 } else { self.pieces as f64 * 60.0 / self.seconds as f64 } }
+}
+
+// "2026 week 42", which sorts in order
+fn week_of(time: i64) -> String {
+    glib::DateTime::from_unix_local(time).ok().and_then(|d| d.format("%G week %V").ok()).map(|s| s.to_string()).unwrap_or_default()
+}
+
+pub mod imp {
+    use std::cell::RefCell;
+
+    use super::{GameRecord, Totals, BTreeMap, load_history, week_of};
+
+    use gtk::{glib, CompositeTemplate};
+    use gtk::glib::clone;
+    use gtk::prelude::*;
+    use gtk::subclass::prelude::*;
+
+    #[derive(Debug, Default)]
+    struct State {
+        records: Vec<GameRecord>,
+        games: Vec<String>,     // the setups in the dropdown after "All games"
+    }
+
+    #[derive(Debug, CompositeTemplate, Default)]
+    #[template(file = "statistics.ui")]
+    pub struct Statistics {
+        state: RefCell<State>,
+
+        #[template_child]
+        game_widget: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        totals_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        weeks_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        close_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Statistics {
+        const NAME: &'static str = "Statistics";
+        type Type = super::Statistics;
+        type ParentType = gtk::ApplicationWindow;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for Statistics {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let stats = self;
+            self.close_button.connect_clicked(clone!(@weak stats => move |_| stats.obj().hide()));
+            self.game_widget.connect_selected_notify(clone!(@weak stats => move |_| stats.build_display()));
+        }
+    }
+
+    impl WidgetImpl for Statistics {}
+    impl WindowImpl for Statistics {}
+    impl ApplicationWindowImpl for Statistics {}
+
+    impl Statistics {
+        // reads the history again each time, the window may have been open over several games
+        pub fn show_statistics(&self, game: &str) {
+            let games = {
+                let mut state = self.state.borrow_mut();
+                state.records = load_history();
+                let mut games: Vec<String> = state.records.iter().map(|r| r.Game.clone()).collect();
+                games.sort();
+                games.dedup();
+                state.games = games.clone();
+                games
+            };
+            let names: Vec<&str> = std::iter::once("All games").chain(games.iter().map(|g| g.as_str())).collect();
+            self.game_widget.set_model(Some(&gtk::StringList::new(&names)));
+            self.game_widget.set_selected(games.iter().position(|g| g == game).map_or(0, |i| i as u32 + 1));
+            self.build_display();
+            self.obj().present();
+        }
+
+        fn build_display(&self) {
+            let state = self.state.borrow();
+            let game = match self.game_widget.selected() {
+                0 => None,
+                n => state.games.get(n as usize - 1),
+            };
+            let records: Vec<&GameRecord> = state.records.iter().filter(|r| game.is_none_or(|g| &r.Game == g)).collect();
+
+            let mut totals = Totals::default();
+            let mut weeks = BTreeMap::<String, Totals>::new();
+            for record in records.iter() {
+                totals.add(record);
+                weeks.entry(week_of(record.Time)).or_default().add(record);
+            }
+
+            clear_grid(&self.totals_grid);
+            let best = totals.best.as_ref().map_or("-".to_string(), |(points, date)| format!("{} points on {}", points, date));
+            let rows = [("Games", totals.games.to_string()),
+                        ("Time played", format!("{}:{:02}:{:02}", totals.seconds / 3600, totals.seconds / 60 % 60, totals.seconds % 60)),
+                        ("Average points", format!("{:.1}", totals.average(totals.points))),
+                        ("Average lines", format!("{:.1}", totals.average(totals.lines))),
+                        ("Pieces per minute", format!("{:.1}", totals.per_minute())),
+                        ("Best game", best)];
+            for (row, (name, value)) in rows.iter().enumerate() {
+                self.totals_grid.attach(&gtk::Label::builder().label(name).halign(gtk::Align::Start).build(), 0, row as i32, 1, 1);
+                self.totals_grid.attach(&gtk::Label::builder().label(value).halign(gtk::Align::Start).build(), 1, row as i32, 1, 1);
+            }
+
+            // the bar shows average lines against the best week, to make the trend easy to see
+            clear_grid(&self.weeks_grid);
+            for (column, title) in ["Week", "Games", "Avg lines", "Avg points", "Pieces/min", ""].iter().enumerate() {
+                self.weeks_grid.attach(&gtk::Label::new(Some(title)), column as i32, 0, 1, 1);
+            }
+            let most = weeks.values().map(|w| w.average(w.lines)).fold(0.0, f64::max).max(1.0);
+            for (i, (week, week_totals)) in weeks.iter().enumerate() {
+                let row = i as i32 + 1;
+                let cells = [week.clone(), week_totals.games.to_string(), format!("{:.1}", week_totals.average(week_totals.lines)),
+                             format!("{:.1}", week_totals.average(week_totals.points)), format!("{:.1}", week_totals.per_minute())];
+                for (column, text) in cells.iter().enumerate() {
+                    self.weeks_grid.attach(&gtk::Label::new(Some(text)), column as i32, row, 1, 1);
+                }
+                let bar = gtk::LevelBar::builder().min_value(0.0).max_value(most).value(week_totals.average(week_totals.lines))
+                    .width_request(150).valign(gtk::Align::Center).build();
+                self.weeks_grid.attach(&bar, 5, row, 1, 1);
+            }
+        }
+    }
+
+    fn clear_grid(grid: &gtk::Grid) {
+        while let Some(child) = grid.first_child() {
+            grid.remove(&child);
+        }
+    }
+}
@@ -17,9 +17,17 @@ impl Summary {
 	}
 }
 
+// The per board counts are points, lines and then how many of each piece, in board::imp::piece_names() order
+pub const COLUMNS: usize = 9;
+
 pub mod imp {
     use std::cell::RefCell;
 
+    use super::COLUMNS;
+    use crate::board::imp::piece_names;
+    use crate::controller::imp::replay::ReplaySettings;
+    use crate::controller::imp::highscores::game_key;
+
     use gtk::{glib, CompositeTemplate};
     use gtk::glib::clone;
     use gtk::prelude::*;
@@ -30,11 +38,20 @@ pub mod imp {
     #[template(file = "summary.ui")]
     pub struct Summary {
         per_board: RefCell<Vec<[u32; 9]>>,
+        groups: RefCell<Vec<(String, Vec<u32>)>>,    // named sets of boards which get a subtotal row
+        game: RefCell<(u32, ReplaySettings)>,        // (seconds played, how the game was set up) for exports
+        chooser: RefCell<Option<gtk::FileChooserNative>>,    // kept while it is open, it goes away otherwise
         
         #[template_child]
         summary_grid: TemplateChild<gtk::Grid>,
         #[template_child]
+        export_csv_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        export_json_button: TemplateChild<gtk::Button>,
+        #[template_child]
         summary_close: TemplateChild<gtk::Button>,
+        #[template_child]
+        export_status: TemplateChild<gtk::Label>,
     }
 
     #[glib::object_subclass]
@@ -66,6 +83,8 @@ pub mod imp {
             self.summary_close.connect_clicked(clone!(@weak summary => move |_| {
 			    summary.obj().hide();
 		    }));
+            self.export_csv_button.connect_clicked(clone!(@weak summary => move |_| summary.choose_export("tetrii-summary.csv")));
+            self.export_json_button.connect_clicked(clone!(@weak summary => move |_| summary.choose_export("tetrii-summary.json")));
         }
     }
 
@@ -85,10 +104,26 @@ pub mod imp {
                 let x: [u32; 9] = Default::default();
                 boards.push(x);
             }
+            self.clear_display();
+            self.export_status.set_label(" ");
+        }    
+
+        // the per board counts, for saving a game
+        pub fn counters(&self) -> Vec<[u32; 9]> { self.per_board.borrow().clone() }
+        pub fn set_counters(&self, counters: &[[u32; 9]]) {
+            let mut boards = self.per_board.borrow_mut();
+            for (board, saved) in boards.iter_mut().zip(counters) { *board = *saved; }
+        }
+
+        pub fn set_groups(&self, groups: Vec<(String, Vec<u32>)>) {
+            *self.groups.borrow_mut() = groups;
+        }
+
+        fn clear_display(&self) {
             while self.summary_grid.child_at(0, 1).is_some() {
                 self.summary_grid.remove_row(1);
             }
-        }    
+        }
 
         pub fn update_entry(&self, id: u32, points: u32, lines: u32, piece: u32) {
             let mut boards = self.per_board.borrow_mut();
@@ -98,14 +133,99 @@ pub mod imp {
             boards[id_usize][2 + piece as usize] += 1;
         }
 
-        pub fn build_display(&self) {
-            let mut totals: [u32; 9] = [0; 9];
+        pub fn set_game(&self, seconds: u32, settings: ReplaySettings) { *self.game.borrow_mut() = (seconds, settings); }
+
+        // each board, then the subtotals, then the total
+        fn rows(&self) -> Vec<(String, [u32; COLUMNS])> {
             let boards = self.per_board.borrow();
-            for i in 0..boards.len() {
-                self.add_line_to_display(&(i + 1).to_string(), (i + 1) as i32, &boards[i]);
-                Summary::add_to_totals(&mut totals, &boards[i]);
+            let mut rows: Vec<(String, [u32; COLUMNS])> = boards.iter().enumerate().map(|(i, b)| ((i + 1).to_string(), *b)).collect();
+            for (name, ids) in self.groups.borrow().iter() {
+                let mut subtotals: [u32; 9] = [0; 9];
+                ids.iter().filter_map(|id| boards.get(*id as usize)).for_each(|b| Summary::add_to_totals(&mut subtotals, b));
+                rows.push((name.clone(), subtotals));
+            }
+            let mut totals: [u32; 9] = [0; 9];
+            boards.iter().for_each(|b| Summary::add_to_totals(&mut totals, b));
+            rows.push(("Total".to_string(), totals));
+            rows
+        }
+
+        pub fn build_display(&self) {
+            self.clear_display();
+            for (i, (name, data)) in self.rows().iter().enumerate() {
+                self.add_line_to_display(name, i as i32 + 1, data);
+            }
+        }
+
+        fn headers() -> Vec<&'static str> { ["Board", "Points", "Lines"].into_iter().chain(piece_names()).collect() }
+
+        // The game comes first as name/value lines, then the table. Names are quoted when they need it.
+        pub fn to_csv(&self) -> String {
+            let (seconds, settings) = self.game.borrow().clone();
+            let mut out = format!("Game,{}\nTime,{:02}:{:02}\nSeconds,{}\n", csv_field(&game_key(&settings)), seconds / 60, seconds % 60, seconds);
+            // then each setting on a line of its own, the same as the JSON has them
+            if let Ok(serde_json::Value::Object(values)) = serde_json::to_value(&settings) {
+                for (name, value) in values.iter() {
+                    out += &format!("{},{}\n", csv_field(name), csv_field(&csv_setting(value)));
+                }
+            }
+            out += "\n";
+            out += &Summary::headers().join(",");
+            out += "\n";
+            for (name, data) in self.rows() {
+                let values: Vec<String> = std::iter::once(csv_field(&name)).chain(data.iter().map(|v| v.to_string())).collect();
+                out += &values.join(",");
+                out += "\n";
             }
-            self.add_line_to_display("Total", self.len() + 1, &totals);
+            out
+        }
+
+        pub fn to_json(&self) -> Result<String, String> {
+            let (seconds, settings) = self.game.borrow().clone();
+            let headers = Summary::headers();
+            let rows: Vec<serde_json::Value> = self.rows().iter().map(|(name, data)| {
+                let mut row = serde_json::Map::new();
+                row.insert(headers[0].to_string(), serde_json::Value::from(name.as_str()));
+                headers[1..].iter().zip(data.iter()).for_each(|(h, v)| { row.insert(h.to_string(), serde_json::Value::from(*v)); });
+                serde_json::Value::Object(row)
+            }).collect();
+            let settings_value = serde_json::to_value(&settings).map_err(|err| err.to_string())?;
+            let export = serde_json::json!({
+                "Game": game_key(&settings),
+                "Time": format!("{:02}:{:02}", seconds / 60, seconds % 60),
+                "Seconds": seconds,
+                "Settings": settings_value,
+                "Columns": headers,
+                "Rows": rows,
+            });
+            serde_json::to_string_pretty(&export).map_err(|err| err.to_string())
+        }
+
+        // JSON for a .json file, CSV for anything else
+        pub fn export(&self, filename: &str) -> Result<String, String> {
+            let text = if filename.ends_with(".json") { self.to_json()? } else { self.to_csv() };
+            std::fs::write(filename, text).map_err(|err| format!("Error writing summary {}: {}", filename, err))?;
+            Ok(format!("Wrote summary to {}", filename))
+        }
+
+        // export() with how it went shown under the buttons
+        pub fn export_reporting(&self, filename: &str) {
+            self.export_status.set_label(&self.export(filename).unwrap_or_else(|err| err));
+        }
+
+        fn choose_export(&self, suggested: &str) {
+            let chooser = gtk::FileChooserNative::new(Some("Export summary"), Some(&*self.obj()), gtk::FileChooserAction::Save, Some("Export"), Some("Cancel"));
+            chooser.set_current_name(suggested);
+            chooser.connect_response(clone!(@weak self as summary => move |chooser, response| {
+                if response == gtk::ResponseType::Accept {
+                    if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                        summary.export_reporting(&path.to_string_lossy());
+                    }
+                }
+                summary.chooser.borrow_mut().take();
+            }));
+            chooser.show();
+            *self.chooser.borrow_mut() = Some(chooser);
         }
 
 	    pub fn add_line_to_display(&self, text: &str, row: i32, data: &[u32; 9]) {
@@ -122,4 +242,17 @@ pub mod imp {
         }
     }
 
-}    
+    fn csv_field(text: &str) -> String {
+        if text.contains([',', '"', '\n']) { format!("\"{}\"", text.replace('"', "\"\"")) } else { text.to_string() }
+    }
+
+    // a setting as one field: strings without their quotes, lists separated by spaces and nothing for unset
+    fn csv_setting(value: &serde_json::Value) -> String {
+        match value {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Array(items) => items.iter().map(csv_setting).collect::<Vec<String>>().join(" "),
+            other => other.to_string(),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod imp;
 
 use crate::controller::imp::summary::Summary;
+use crate::controller::imp::highscores::HighScores;
+use crate::controller::imp::statistics::Statistics;
 
 use gtk::{gio, glib};
 use gtk::glib::closure_local;
@@ -20,6 +22,8 @@ impl Controller {
     fn new<P: glib::IsA<gtk::Application>>(app: &P, count: u32, width: u32, height: u32, preview: bool) -> Self {
         let controller: Controller = glib::Object::builder().property("application", app).build();
         controller.imp().internal.borrow_mut().summary = Some(Summary::new(app));
+        controller.imp().internal.borrow_mut().highscores = Some(HighScores::new(app));
+        controller.imp().internal.borrow_mut().statistics = Some(Statistics::new(app));
         controller.imp().initialize(count, width, height, preview);
         
         controller.set_focusable(true);
@@ -40,15 +44,36 @@ impl Controller {
         controller.connect_closure(
             "mouse-click",
             false,
-            closure_local!(|ctrlr: Controller, id: u32, button: u32, | {
-                let _ = &ctrlr.imp().mouse_click(id, button);
+            closure_local!(|ctrlr: Controller, id: u32, button: u32, mods: u32, | {
+                let _ = &ctrlr.imp().mouse_click(id, button, mods);
             }),
         );
         controller.connect_closure(
             "select",
             false,
             closure_local!(|ctrlr: Controller, id: u32, | {
-                let _ = &ctrlr.imp().set_board(id);
+                let _ = &ctrlr.imp().board_entered(id);
+            }),
+        );
+        controller.connect_closure(
+            "mouse-wheel",
+            false,
+            closure_local!(|ctrlr: Controller, id: u32, down: u32, mods: u32, | {
+                let _ = &ctrlr.imp().mouse_wheel(id, down, mods);
+            }),
+        );
+        controller.connect_closure(
+            "touch",
+            false,
+            closure_local!(|ctrlr: Controller, id: u32, touch: u32, count: u32, | {
+                let _ = &ctrlr.imp().touch(id, touch, count);
+            }),
+        );
+        controller.connect_closure(
+            "mouse-drag",
+            false,
+            closure_local!(|ctrlr: Controller, id: u32, button: u32, column: u32, mods: u32, | {
+                let _ = &ctrlr.imp().mouse_drag(id, button, column, mods);
             }),
         );
         controller
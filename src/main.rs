@@ -8,33 +8,73 @@ mod controller;
 use config::Config;
 use options::Options;
 use board::Board;
+use controller::imp::replay::Replay;
 
 use gtk::prelude::*;
-use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::subclass::prelude::{ObjectSubclassIsExt, ObjectSubclassExt};
 
 use once_cell::sync::Lazy;
 
 const APP_ID: &str = "com.young-0.tetrii.rust";
 
 fn main() {
-    let config = Config::build_config();
-    gtk::init().expect("Error initializing gtk");
+    let mut config = Config::build_config();
+    config.problems.iter().for_each(|problem| eprintln!("{}", problem));
+    // a replay brings its own boards and rules
+    let replay = config.replay.as_ref().map(|file| match Replay::load(file) {
+        Ok(replay) => replay,
+        Err(err) => { eprintln!("{}", err); std::process::exit(1); },
+    });
+    if let Some(replay) = &replay {
+        replay.Settings.apply_to(&mut config);
+        // replays get passed around, and settings out of range would make boards that can't be played
+        let checked = config.problems.len();
+        config.check_values();
+        if config.problems.len() > checked {
+            let file = config.replay.clone().unwrap_or_default();
+            config.problems[checked..].iter().for_each(|problem| eprintln!("Error in replay {}: {}", file, problem.message));
+            std::process::exit(1);
+        }
+    }
+    if let Err(err) = gtk::init() {
+        eprintln!("Error initializing gtk: {}", err);
+        std::process::exit(1);
+    }
     let app = gtk::Application::new( Some(APP_ID), Default::default(), );
-    let height = config.height;
-    let width = config.width;
-    let preview = config.preview;
-    let cell_size = config.cell_size;
     app.connect_activate(move |appx| {
         //let win = Board::new(app, 10, 20, 0);
         let  options = Options::new(appx);
-		options::imp::load_style_from_file("style.css");
-        options.set_values(config.boards, width, height, cell_size, preview);
+		options::imp::load_style_from_file(&config.style);
+        options.set_values(&config);
 		options.make_controller();
 		unsafe {OPTIONS = Some(options); }
+		controller_inst().set_summary_out(config.summary_out.clone());
+		if let Some(replay) = &replay { controller_inst().start_playback(replay.clone()); }
+		controller::imp::gamepad::start(config.gamepads, &config.pad_events, |event| controller_inst().pad_event(event));
+		if !config.problems.is_empty() { show_problems(&config); }
     });
     let empty: Vec<String> = vec![];  // thanks to stackoverflow, I learned EMPTY is needed to keep GTK from interpreting the command line flags
     app.run_with_args(&empty);
 }
+// Bad values have already been replaced by the defaults, so the game can go on with them if the player wants
+fn show_problems(config: &Config) {
+    let text: Vec<String> = config.problems.iter().map(|p| p.to_string()).collect();
+    let dialog = gtk::MessageDialog::builder()
+        .transient_for(&*controller_inst().obj())
+        .modal(true)
+        .message_type(gtk::MessageType::Warning)
+        .text("There are problems with the configuration")
+        .secondary_text(&text.join("\n"))
+        .build();
+    dialog.add_button("Quit", gtk::ResponseType::Cancel);
+    dialog.add_button("Continue with defaults", gtk::ResponseType::Ok);
+    dialog.connect_response(|dialog, response| {
+        if response != gtk::ResponseType::Ok { std::process::exit(1); }
+        dialog.destroy();
+    });
+    dialog.present();
+}
+
 fn exit() {
 	controller_inst().destroy();
 	options_inst().destroy();
@@ -56,5 +96,6 @@ static mut CONTROLLER: Option<crate::controller::Controller> = None;
 static mut OPTIONS: Option<crate::options::Options> = None;
 
 fn board(which: usize) -> &'static Board { unsafe { &BOARDS[which] } }
+fn board_count() -> usize { unsafe { BOARDS.len() } }
 fn controller_inst<'a>() -> &'a crate::controller::imp::Controller { unsafe { CONTROLLER.as_ref().unwrap().imp() }}
 fn options_inst<'a>() -> &'a crate::options::imp::Options { unsafe { OPTIONS.as_ref().unwrap().imp() }}
@@ -1,8 +1,14 @@
+use crate::config::{Config, Team};
 use crate::controller::Controller;
+use crate::controller::imp::{FocusPolicy, AutoRepeat};
+use crate::board::imp::LineRule;
+use crate::controller::imp::keymap::{Keymap, DEFAULT_PROFILE};
+use super::keymap_editor::KeymapEditor;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::fs;
 use std::env;
+use std::path::{Path, PathBuf};
 
 
 use gtk::{glib, CompositeTemplate};
@@ -19,9 +25,30 @@ struct Internal {
 	width: u32,
     cell_size: u32, 
 	preview: bool,
+	focus: FocusPolicy,
+	players: u32,
+	teams: Vec<Team>,    // only set from the config, the window has no editor for them
+	linked: Option<LineRule>,
+	ramp: (u32, u32),    // (lines, minutes)
+	keymaps: Vec<(String, Keymap)>,   // the profiles from the config, the first is always the default
+	key_profile: usize,
+	config_file: String,
+	editor: Option<KeymapEditor>,     // made the first time it is needed
+	auto_repeat: AutoRepeat,          // only set from the config
+	auto_pause: bool,
+	replay_dir: String,               // only set from the config
+	save_file: String,                // only set from the config
+	delay: f64,                       // only set from the config
+	config: Option<Config>,           // what set_values() was given, for the settings the window doesn't show
+	applied: Option<Config>,          // what the game is using, which Cancel goes back to
+	listing_profiles: bool,           // set while the profile dropdown is being filled, so that isn't taken as a choice
 }
 impl Default for Internal {
-    fn default() -> Internal { Internal { count: 2, height: 20, width: 10, cell_size: 25, preview: true, }}
+    fn default() -> Internal { Internal { count: 2, height: 20, width: 10, cell_size: 25, preview: true, focus: FocusPolicy::default(), players: 1,
+										  teams: Vec::new(), linked: None, ramp: (0, 0),
+										  keymaps: vec![(DEFAULT_PROFILE.to_string(), Keymap::default())], key_profile: 0, config_file: String::new(), editor: None,
+										  auto_repeat: AutoRepeat::default(), auto_pause: true, replay_dir: String::new(), save_file: String::new(), delay: 0.0, config: None, applied: None,
+										  listing_profiles: false, }}
 }
 
 //#[derive(Debug, Default)]
@@ -44,6 +71,28 @@ pub struct Options {
     pub cancel_button: TemplateChild<gtk::Button>,
     #[template_child]
     pub preview_check: TemplateChild<gtk::CheckButton>,
+    #[template_child]
+    pub focus_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub players_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub linked_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub ramp_lines_widget: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub ramp_minutes_widget: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub keymap_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub keys_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub auto_pause_check: TemplateChild<gtk::CheckButton>,
+    #[template_child]
+    pub profile_widget: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub save_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub save_status: TemplateChild<gtk::Label>,
     //    pub grid: gtk::Grid,
 }
 
@@ -79,14 +128,23 @@ impl ObjectImpl for Options {
         self.parent_constructed();
         let options = self;
         self.cancel_button.connect_clicked(clone!(@weak options => move |_| {
-			options.set_display_from_values();
+			let applied = options.internal.borrow().applied.clone();
+			match applied {
+				Some(config) => options.load_config(&config),
+				None => options.set_display_from_values(),
+			}
 			options.obj().hide();
 		}));
         self.apply_button.connect_clicked(clone!(@weak options => move |_| {
 			options.set_values_from_display();
 			options.remake_controller();
+			let applied = options.to_config();
+			options.internal.borrow_mut().applied = applied;
 			options.obj().hide();
 		}));
+        self.keys_button.connect_clicked(clone!(@weak options => move |_| { options.edit_keys(); }));
+        self.save_button.connect_clicked(clone!(@weak options => move |_| { options.save_as_default(); }));
+        self.profile_widget.connect_selected_notify(clone!(@weak options => move |_| { options.profile_selected(); }));
         // I'm sure this can be done in the template file, but I couldn't find how, either in the doc or testing. I tried
         // setting the "selected" and "selected-item" properties but they did not work
         self.width_widget.set_property("selected", 2u32);
@@ -104,12 +162,34 @@ impl Options {
 	pub fn destroy(&self) { self.obj().destroy(); }
 
 	// inject values into options, store in struct and display in ui
-    pub fn set_values(&self, count: u32, width: u32, height: u32, cell_size: u32, preview: bool) {
+    pub fn set_values(&self, config: &Config) {
+		self.load_config(config);
+		self.internal.borrow_mut().applied = Some(config.clone());
+	}
+
+	// the same without changing what Cancel goes back to, for switching profiles
+	fn load_config(&self, config: &Config) {
 		{
 			let mut internal = self.internal.borrow_mut();
-            if internal.cell_size != cell_size { set_cellsize(cell_size); }
-			(internal.count, internal.width, internal.height, internal.cell_size, internal.preview) = (count, width, height, cell_size, preview);
+            if internal.cell_size != config.cell_size { set_cellsize(config.cell_size); }
+			(internal.count, internal.width, internal.height, internal.cell_size, internal.preview) =
+				(config.boards, config.width, config.height, config.cell_size, config.preview);
+			(internal.focus, internal.players) = (config.focus, config.players);
+			internal.teams = config.teams.clone();
+			internal.linked = config.linked;
+			internal.ramp = (config.ramp_lines, config.ramp_minutes);
+			internal.keymaps = config.keymaps.clone();
+			internal.key_profile = config.keymaps.iter().position(|k| k.0 == config.key_profile).unwrap_or(0);
+			internal.config_file = config.config_file.clone();
+			internal.auto_pause = config.auto_pause;
+			internal.replay_dir = config.replay_dir.clone();
+			internal.save_file = config.save_file.clone();
+			internal.delay = config.delay;
+			internal.auto_repeat = AutoRepeat { delay: config.das, rate: config.arr, soft_drop: config.soft_drop, };
+			internal.config = Some(config.clone());
 		}
+		self.list_profiles(config);
+		self.save_status.set_label(" ");
 		self.set_display_from_values();
 	}
 
@@ -121,6 +201,15 @@ impl Options {
         self.height_widget.set_property("selected", internal.height - 10);
         self.cell_widget.set_property("selected", (internal.cell_size - 10)/5);
         self.preview_check.set_active(internal.preview);
+        self.focus_widget.set_property("selected", internal.focus.index());
+        self.players_widget.set_property("selected", internal.players - 1);
+        self.linked_widget.set_property("selected", match internal.linked { None => 0u32, Some(LineRule::Board) => 1, Some(LineRule::Field) => 2, });
+        self.ramp_lines_widget.set_value(internal.ramp.0 as f64);
+        self.ramp_minutes_widget.set_value(internal.ramp.1 as f64);
+        let names: Vec<&str> = internal.keymaps.iter().map(|k| k.0.as_str()).collect();
+        self.keymap_widget.set_model(Some(&gtk::StringList::new(&names)));
+        self.keymap_widget.set_property("selected", internal.key_profile as u32);
+        self.auto_pause_check.set_active(internal.auto_pause);
     }
 
 	// update struct values from display
@@ -137,18 +226,130 @@ impl Options {
             cell_pixels,
             self.preview_check.is_active(),
         );
+		internal.focus = FocusPolicy::from_index(self.focus_widget.selected());
+		internal.players = self.players_widget.selected() + 1;
+		internal.linked = match self.linked_widget.selected() { 1 => Some(LineRule::Board), 2 => Some(LineRule::Field), _ => None, };
+		internal.ramp = (self.ramp_lines_widget.value_as_int() as u32, self.ramp_minutes_widget.value_as_int() as u32);
+		internal.auto_pause = self.auto_pause_check.is_active();
+		internal.key_profile = (self.keymap_widget.selected() as usize).min(internal.keymaps.len() - 1);
 	}
         
     pub fn make_controller(&self, ) {
 		let internal = self.internal.borrow();
         Controller::new_ref(&self.obj().application().unwrap(),internal.count, internal.width, internal.height, internal.preview)
             .show();
+		crate::controller_inst().set_focus_policy(internal.focus);
+		crate::controller_inst().set_players(internal.players);
+		crate::controller_inst().set_teams(internal.teams.clone());
+		crate::controller_inst().set_linked(internal.linked);
+		crate::controller_inst().set_ramp(internal.ramp.0, internal.ramp.1);
+		crate::controller_inst().set_keymap(internal.keymaps[internal.key_profile].1.clone());
+		crate::controller_inst().set_auto_repeat(internal.auto_repeat);
+		crate::controller_inst().set_auto_pause(internal.auto_pause);
+		crate::controller_inst().set_replay_dir(&internal.replay_dir);
+		crate::controller_inst().set_save_file(&internal.save_file);
+		crate::controller_inst().set_delay(internal.delay);
     }
 
+    // A profile's Style is not loaded here: the stylesheet is only read at startup, so a new one takes a restart.
     pub fn remake_controller(&self, ) {
 		let internal = self.internal.borrow();
 		let controller = crate::controller_inst();
+		controller.set_players(internal.players);
+		controller.set_linked(internal.linked);
+		controller.set_ramp(internal.ramp.0, internal.ramp.1);
 		controller.initialize(internal.count, internal.width, internal.height, internal.preview);
+		controller.set_focus_policy(internal.focus);
+		controller.set_teams(internal.teams.clone());
+		controller.set_keymap(internal.keymaps[internal.key_profile].1.clone());
+		controller.set_auto_repeat(internal.auto_repeat);
+		controller.set_auto_pause(internal.auto_pause);
+		controller.set_replay_dir(&internal.replay_dir);
+		controller.set_save_file(&internal.save_file);
+		controller.set_delay(internal.delay);
+	}
+
+	// the first entry is no profile, the top level values in the config file
+	fn list_profiles(&self, config: &Config) {
+		self.internal.borrow_mut().listing_profiles = true;
+		let names: Vec<&str> = std::iter::once("None").chain(config.profiles.iter().map(|p| p.as_str())).collect();
+		self.profile_widget.set_model(Some(&gtk::StringList::new(&names)));
+		let selected = config.profile.as_ref().and_then(|name| config.profiles.iter().position(|p| p == name)).map_or(0, |i| i + 1);
+		self.profile_widget.set_selected(selected as u32);
+		self.internal.borrow_mut().listing_profiles = false;
+	}
+
+	// shows the values of the profile picked. They are used once Apply is clicked.
+	fn profile_selected(&self) {
+		let name = {
+			let internal = self.internal.borrow();
+			if internal.listing_profiles { return; }
+			let Some(config) = internal.config.as_ref() else { return; };
+			let name = match self.profile_widget.selected() {
+				0 => String::new(),
+				n => match config.profiles.get(n as usize - 1) { Some(name) => name.clone(), None => return, },
+			};
+			if name == config.profile.clone().unwrap_or_default() { return; }
+			name
+		};
+		let config = Config::with_profile(&name);
+		self.load_config(&config);
+		let problems: Vec<String> = config.problems.iter().map(|p| p.to_string()).collect();
+		if !problems.is_empty() { self.save_status.set_label(&problems.join("\n")); }
+	}
+
+	// The config as it is now: what it was started with, changed by whatever has been set here since
+	fn to_config(&self) -> Option<Config> {
+		let internal = self.internal.borrow();
+		let mut config = internal.config.clone()?;
+		(config.boards, config.width, config.height, config.cell_size, config.preview) =
+			(internal.count, internal.width, internal.height, internal.cell_size, internal.preview);
+		(config.focus, config.players, config.linked) = (internal.focus, internal.players, internal.linked);
+		(config.ramp_lines, config.ramp_minutes) = internal.ramp;
+		config.teams = internal.teams.clone();
+		config.keymaps = internal.keymaps.clone();
+		config.key_profile = internal.keymaps[internal.key_profile].0.clone();
+		config.auto_pause = internal.auto_pause;
+		(config.das, config.arr, config.soft_drop) = (internal.auto_repeat.delay, internal.auto_repeat.rate, internal.auto_repeat.soft_drop);
+		Some(config)
+	}
+
+	// applies the settings, the same as Apply, and writes them to the config file. The window stays open to show how
+	// that went.
+	fn save_as_default(&self) {
+		self.set_values_from_display();
+		self.remake_controller();
+		let Some(config) = self.to_config() else { return; };
+		let result = config.save(&config.config_file);
+		self.save_status.set_label(&result.unwrap_or_else(|err| err));
+		let mut internal = self.internal.borrow_mut();
+		internal.applied = Some(config.clone());
+		internal.config = Some(config);
+	}
+
+	// opens the editor on the profile selected in the dropdown, which may not be the one in use yet
+	fn edit_keys(&self) {
+		let index = (self.keymap_widget.selected() as usize).min(self.internal.borrow().keymaps.len() - 1);
+		if self.internal.borrow().editor.is_none() {
+			let editor = KeymapEditor::new(&self.obj().application().unwrap());
+			editor.set_transient_for(Some(&*self.obj()));
+			self.internal.borrow_mut().editor = Some(editor);
+		}
+		let internal = self.internal.borrow();
+		let (name, keymap) = internal.keymaps[index].clone();
+		internal.editor.as_ref().unwrap().imp().edit(&name, keymap, &internal.config_file);
+	}
+
+	// called by the editor when it closes or saves. The keys take effect right away if the profile is the one in use
+	pub fn keymap_edited(&self, name: &str, keymap: Keymap) {
+		let mut internal = self.internal.borrow_mut();
+		if let Some(index) = internal.keymaps.iter().position(|k| k.0 == name) {
+			internal.keymaps[index].1 = keymap.clone();
+			if let Some(applied) = internal.applied.as_mut() {
+				if let Some(k) = applied.keymaps.iter_mut().find(|k| k.0 == name) { k.1 = keymap.clone(); }
+			}
+			if index == internal.key_profile { crate::controller_inst().set_keymap(keymap); }
+		}
 	}
 }
 
@@ -167,18 +368,42 @@ pub fn read_style(css_data: &[u8]) {
     );
 }
 
+// used when the stylesheet can't be found, so the game still looks right
+const BUILT_IN_STYLE: &[u8] = include_bytes!("../../style.css");
+
+// Loads the stylesheet FILENAME, found by find_style(). Without one the built in copy is used.
 pub fn load_style_from_file(filename: &str) {
-    let mut path = env::current_dir().unwrap();
-	path.push(filename);
-    let mut css_data = fs::read(&path);//.expect("could not find CSS file");
-	while css_data.is_err() {
-		path.pop();
-		if !path.pop() {
-			panic!("Cannot find file {} anywhere on the current trunk, file is required for program to run", filename);
-		}
-		path.push(filename);
-		css_data = fs::read(&path);//.expect("could not find CSS file");
-	}
-    read_style(&css_data.unwrap());
+    match find_style(filename).map(|path| (fs::read(&path), path)) {
+        Some((Ok(css_data), _)) => read_style(&css_data),
+        Some((Err(err), path)) => {
+            eprintln!("Could not read stylesheet {}: {}, using the built in one", path.display(), err);
+            read_style(BUILT_IN_STYLE);
+        },
+        None => read_style(BUILT_IN_STYLE),
+    }
 }
 
+// A stylesheet named with a path is used as it is. A bare name is looked for in the current directory, then in tetrii
+// under the user's XDG config and data directories and the system ones, and last in the directories above the current
+// one, which finds the one in the source tree when running from target/.
+pub fn find_style(filename: &str) -> Option<PathBuf> {
+    let name = Path::new(filename);
+    if name.is_absolute() || name.parent().is_some_and(|p| !p.as_os_str().is_empty()) {
+        return name.is_file().then(|| name.to_path_buf());
+    }
+    let xdg_dirs = |var: &str, default: &str| -> Vec<PathBuf> {
+        env::var(var).ok().filter(|v| !v.is_empty()).unwrap_or(default.to_string())
+            .split(':').filter(|d| !d.is_empty()).map(PathBuf::from).collect()
+    };
+    let cwd = env::current_dir().ok();
+    let mut dirs: Vec<PathBuf> = dirs::config_dir().into_iter().collect();
+    dirs.extend(xdg_dirs("XDG_CONFIG_DIRS", "/etc/xdg"));
+    dirs.extend(dirs::data_dir());
+    dirs.extend(xdg_dirs("XDG_DATA_DIRS", "/usr/local/share:/usr/share"));
+    let mut places: Vec<PathBuf> = cwd.iter().map(|dir| dir.join(name)).collect();
+    places.extend(dirs.iter().map(|dir| dir.join("tetrii").join(name)));
+    if let Some(cwd) = cwd {
+        places.extend(cwd.ancestors().skip(1).map(|dir| dir.join(name)));
+    }
+    places.into_iter().find(|path| path.is_file())
+}
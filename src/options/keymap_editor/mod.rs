@@ -0,0 +1,219 @@
+use gtk::{gio, glib};
+
+glib::wrapper! {
+    pub struct KeymapEditor(ObjectSubclass<imp::KeymapEditor>)
+    @extends gtk::Widget, gtk::Window, gtk::ApplicationWindow, @implements gio::ActionMap, gio::ActionGroup;
+}
+
+impl KeymapEditor {
+    pub fn new<P: glib::IsA<gtk::Application>>(app: &P, ) -> Self {
+		glib::Object::builder().property("application", app).build()
+	}
+}
+
+pub mod imp {
+    use std::cell::RefCell;
+
+    use crate::controller::imp::Command;
+    use crate::controller::imp::keymap::{Keymap, key_string, is_modifier_key, base_key_name, physical_name};
+    use crate::config::save_key_profile;
+
+    use gtk::{glib, CompositeTemplate};
+    use gtk::glib::clone;
+    use gtk::prelude::*;
+    use gtk::subclass::prelude::*;
+
+    // The editor works on a copy of one keymap profile, it goes back to the Options window when the editor is closed
+    #[derive(Debug, Default)]
+    struct State {
+        profile: String,
+        keymap: Keymap,
+        section: usize,                 // 0 is the keys for everyone, 1 on are the players
+        capturing: Option<Command>,     // waiting for a key or button to bind to this
+        config_file: String,
+    }
+
+    #[derive(Debug, CompositeTemplate, Default)]
+    #[template(file = "keymap_editor.ui")]
+    pub struct KeymapEditor {
+        state: RefCell<State>,
+
+        #[template_child]
+        profile_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        section_widget: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        physical_check: TemplateChild<gtk::CheckButton>,
+        #[template_child]
+        bindings_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        status: TemplateChild<gtk::Label>,
+        #[template_child]
+        defaults_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        save_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        close_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for KeymapEditor {
+        const NAME: &'static str = "KeymapEditor";
+        type Type = super::KeymapEditor;
+        type ParentType = gtk::ApplicationWindow;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for KeymapEditor {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let editor = self;
+            self.close_button.connect_clicked(clone!(@weak editor => move |_| {
+                editor.finish();
+                editor.obj().hide();
+		    }));
+            self.save_button.connect_clicked(clone!(@weak editor => move |_| { editor.save(); }));
+            self.defaults_button.connect_clicked(clone!(@weak editor => move |_| {
+                editor.state.borrow_mut().keymap = Keymap::default();
+                editor.status.set_label("Restored the built in keys");
+                editor.rebuild();
+		    }));
+            self.section_widget.connect_selected_notify(clone!(@weak editor => move |dropdown| {
+                editor.state.borrow_mut().section = dropdown.selected() as usize;
+                editor.rebuild();
+            }));
+
+            // keys and buttons are only taken while capturing, otherwise they go on to the widgets as usual
+            let key_handler = gtk::EventControllerKey::new();
+            key_handler.connect_key_pressed(clone!(@weak editor => @default-return gtk::Inhibit(false), move |ctlr, key, code, mods| {
+                if editor.state.borrow().capturing.is_none() { return gtk::Inhibit(false); }
+                let name = base_key_name(key, code, ctlr.group());
+                if name == "Escape" { editor.cancel_capture(); }
+                else if !name.is_empty() && !is_modifier_key(&name) {
+                    let name = if editor.physical_check.is_active() { physical_name(code).unwrap_or(name) } else { name };
+                    editor.capture(key_string(&name, mods));
+                }
+                gtk::Inhibit(true)
+            }));
+            self.obj().add_controller(&key_handler);
+            let gesture = gtk::GestureClick::new();
+            gesture.set_button(0);
+            gesture.set_propagation_phase(gtk::PropagationPhase::Capture);
+            gesture.connect_pressed(clone!(@weak editor => move |gesture, _n, _x, _y| {
+                let capturing = editor.state.borrow().capturing;
+                if capturing.is_none() { return; }
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                // a button given to Follow is bound as a drag, there is nothing else a drag can do
+                let kind = if capturing == Some(Command::Follow) { "Drag" } else { "Mouse" };
+                editor.capture(key_string(&format!("{}{}", kind, gesture.current_button()), gesture.current_event_state()));
+            }));
+            self.obj().add_controller(&gesture);
+            let scroll = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL | gtk::EventControllerScrollFlags::DISCRETE);
+            scroll.set_propagation_phase(gtk::PropagationPhase::Capture);
+            scroll.connect_scroll(clone!(@weak editor => @default-return gtk::Inhibit(false), move |ctlr, _dx, dy| {
+                if editor.state.borrow().capturing.is_none() || dy == 0.0 { return gtk::Inhibit(false); }
+                editor.capture(key_string(if dy < 0.0 { "WheelUp" } else { "WheelDown" }, ctlr.current_event_state()));
+                gtk::Inhibit(true)
+            }));
+            self.obj().add_controller(&scroll);
+        }
+    }
+
+    impl WidgetImpl for KeymapEditor {}
+    impl WindowImpl for KeymapEditor {}
+    impl ApplicationWindowImpl for KeymapEditor {}
+
+    //////////////////////////////////////////////////////////////////
+
+    impl KeymapEditor {
+        pub fn edit(&self, profile: &str, keymap: Keymap, config_file: &str) {
+            {
+                let mut state = self.state.borrow_mut();
+                (state.profile, state.keymap, state.config_file) = (profile.to_string(), keymap, config_file.to_string());
+                state.capturing = None;
+            }
+            self.profile_label.set_label(&format!("Keys `{}`", profile));
+            self.status.set_label("Keys shown in red are also bound in another section, only the first one is used");
+            self.rebuild();
+            self.obj().show();
+        }
+
+        // one row per command: name, keys, and buttons to add a key or clear them all
+        fn rebuild(&self) {
+            let editor = self;
+            while let Some(child) = self.bindings_grid.first_child() {
+                self.bindings_grid.remove(&child);
+            }
+            let state = self.state.borrow();
+            let conflicts = state.keymap.conflicts(state.section);
+            for (row, command) in state.keymap.commands().into_iter().enumerate() {
+                let keys = state.keymap.keys_for(state.section, command);
+                let name = gtk::Label::builder().label(&state.keymap.command_name(command)).halign(gtk::Align::Start).build();
+                let bound = gtk::Label::builder().label(&keys.join(", ")).halign(gtk::Align::Start).hexpand(true).build();
+                if keys.iter().any(|k| conflicts.contains(k)) { bound.add_css_class("conflict"); }
+                if state.capturing == Some(command) { bound.set_label("press a key, mouse button or the wheel, Escape to cancel"); }
+                let add = gtk::Button::with_label("Add");
+                add.connect_clicked(clone!(@weak editor => move |_| { editor.start_capture(command); }));
+                let clear = gtk::Button::with_label("Clear");
+                clear.connect_clicked(clone!(@weak editor => move |_| {
+                    { let mut state = editor.state.borrow_mut(); let section = state.section; state.keymap.unbind(section, command); }
+                    editor.rebuild();
+                }));
+                self.bindings_grid.attach(&name, 0, row as i32, 1, 1);
+                self.bindings_grid.attach(&bound, 1, row as i32, 1, 1);
+                self.bindings_grid.attach(&add, 2, row as i32, 1, 1);
+                self.bindings_grid.attach(&clear, 3, row as i32, 1, 1);
+            }
+        }
+
+        fn start_capture(&self, command: Command) {
+            self.state.borrow_mut().capturing = Some(command);
+            self.rebuild();
+        }
+
+        fn cancel_capture(&self) {
+            self.state.borrow_mut().capturing = None;
+            self.rebuild();
+        }
+
+        fn capture(&self, key: String) {
+            let status = {
+                let mut state = self.state.borrow_mut();
+                let command = match state.capturing.take() { Some(c) => c, None => return, };
+                let section = state.section;
+                let name = state.keymap.command_name(command);
+                match state.keymap.bind(section, &key, command) {
+                    Some(previous) => format!("{} was {}, now it is {}", key, state.keymap.command_name(previous), name),
+                    None => format!("{} is {}", key, name),
+                }
+            };
+            self.status.set_label(&status);
+            self.rebuild();
+        }
+
+        // hands the edited keymap back to the Options window
+        fn finish(&self) {
+            let state = self.state.borrow();
+            crate::options_inst().keymap_edited(&state.profile, state.keymap.clone());
+        }
+
+        fn save(&self) {
+            self.finish();
+            let result = {
+                let state = self.state.borrow();
+                save_key_profile(&state.config_file, &state.profile, &state.keymap)
+            };
+            match result {
+                Ok(message) => self.status.set_label(&message),
+                Err(message) => self.status.set_label(&message),
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
 pub mod imp;
+pub mod keymap_editor;
 
 use gtk::subclass::prelude::*;
 use gtk::{gio, glib};
+use crate::config::Config;
 
 glib::wrapper! {
     pub struct Options(ObjectSubclass<imp::Options>)
@@ -11,8 +13,8 @@ glib::wrapper! {
 impl Options {
     pub fn new<P: glib::IsA<gtk::Application>>(app: &P) -> Self { glib::Object::builder().property("application", app).build() }
 
-    pub fn set_values(&self, count: u32, width: u32, height: u32, cell_size: u32, preview: bool) {
-        self.imp().set_values(count, width, height, cell_size, preview);
+    pub fn set_values(&self, config: &Config) {
+        self.imp().set_values(config);
     }
 
     pub fn make_controller(&self, ) { self.imp().make_controller(); }